@@ -0,0 +1,42 @@
+//! The DWT cycle counter, extended to 64 bits
+//!
+//! CYCCNT is 32 bits wide, it wraps after about 9 minutes at 8 MHz and after a minute at 72 MHz.
+//! `now` notices the wraps and counts them, as long as it is called at least once per wrap.
+//! `aux11`'s `MonoTimer` makes sure of that by calling it from the SysTick exception.
+//!
+//! CYCCNT only counts while the core runs, it stands still during WFI.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use cortex_m::{interrupt, peripheral::DWT};
+
+// Upper 32 bits of the extended cycle count
+static HIGH: AtomicU32 = AtomicU32::new(0);
+// CYCCNT when the extended cycle count was last updated, a smaller value means it has wrapped
+static LAST: AtomicU32 = AtomicU32::new(0);
+
+/// Starts the cycle counter, from then on it can't be stopped or reset
+pub fn start(mut dwt: DWT) {
+    dwt.enable_cycle_counter();
+}
+
+/// Core clock cycles since `start`
+pub fn now() -> u64 {
+    interrupt::free(|_| {
+        let low = cycle_count();
+        let mut high = HIGH.load(Ordering::Relaxed);
+
+        if low < LAST.load(Ordering::Relaxed) {
+            high += 1;
+            HIGH.store(high, Ordering::Relaxed);
+        }
+        LAST.store(low, Ordering::Relaxed);
+
+        u64::from(high) << 32 | u64::from(low)
+    })
+}
+
+#[allow(deprecated)] // NOTE(allow) `DWT::cycle_count`, its replacement, is not in cortex-m 0.6
+fn cycle_count() -> u32 {
+    DWT::get_cycle_count()
+}
@@ -0,0 +1,110 @@
+//! SysTick delay that sleeps instead of spinning
+//!
+//! Same `DelayMs` / `DelayUs` surface as the HAL's `Delay`, but the core waits for the SysTick
+//! exception with WFI, which uses a lot less power. The exception only has to end WFI, but it
+//! needs a handler (the default one never returns), so the application has to define one:
+//!
+//! ``` ignore
+//! #[exception]
+//! fn SysTick() {}
+//! ```
+//!
+//! SysTick runs on the core clock, which is HCLK, so that is the frequency `new` takes:
+//!
+//! ``` ignore
+//! let delay = Delay::new(cp.SYST, clocks.hclk().0);
+//! ```
+
+use cortex_m::{
+    asm, interrupt,
+    peripheral::{syst::SystClkSource, SYST},
+};
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+// SysTick is a 24-bit counter, and a period is RELOAD + 1 cycles long
+const MAX_PERIOD: u64 = 0x0100_0000;
+
+/// SysTick as a low power delay provider
+pub struct Delay {
+    syst: SYST,
+    // Core clock (HCLK) frequency, in Hz
+    hclk: u32,
+}
+
+impl Delay {
+    pub fn new(mut syst: SYST, hclk: u32) -> Self {
+        syst.set_clock_source(SystClkSource::Core);
+
+        Delay { syst, hclk }
+    }
+
+    /// Gives SysTick back
+    pub fn free(self) -> SYST {
+        self.syst
+    }
+
+    // Sleeps for `cycles` core clock cycles, in as many SysTick periods as needed. A period can't
+    // be shorter than 2 cycles (RELOAD = 0 stops SysTick), a last cycle left over is not slept
+    fn sleep(&mut self, mut cycles: u64) {
+        while cycles >= 2 {
+            let period = cycles.min(MAX_PERIOD);
+            cycles -= period;
+
+            self.syst.set_reload(period as u32 - 1);
+            self.syst.clear_current();
+            self.syst.enable_interrupt();
+            self.syst.enable_counter();
+
+            let mut wrapped = false;
+            while !wrapped {
+                // SysTick can't fire between the check and WFI, and if it fires later it still
+                // ends WFI. Reading COUNTFLAG clears it, hence `wrapped`
+                interrupt::free(|_| {
+                    wrapped = self.syst.has_wrapped();
+                    if !wrapped {
+                        asm::wfi();
+                    }
+                });
+            }
+
+            self.syst.disable_counter();
+            self.syst.disable_interrupt();
+        }
+    }
+}
+
+impl DelayUs<u32> for Delay {
+    fn delay_us(&mut self, us: u32) {
+        self.sleep(u64::from(us) * u64::from(self.hclk) / 1_000_000);
+    }
+}
+
+impl DelayUs<u16> for Delay {
+    fn delay_us(&mut self, us: u16) {
+        self.delay_us(u32::from(us));
+    }
+}
+
+impl DelayUs<u8> for Delay {
+    fn delay_us(&mut self, us: u8) {
+        self.delay_us(u32::from(us));
+    }
+}
+
+impl DelayMs<u32> for Delay {
+    fn delay_ms(&mut self, ms: u32) {
+        self.sleep(u64::from(ms) * u64::from(self.hclk) / 1_000);
+    }
+}
+
+impl DelayMs<u16> for Delay {
+    fn delay_ms(&mut self, ms: u16) {
+        self.delay_ms(u32::from(ms));
+    }
+}
+
+impl DelayMs<u8> for Delay {
+    fn delay_ms(&mut self, ms: u8) {
+        self.delay_ms(u32::from(ms));
+    }
+}
@@ -0,0 +1,24 @@
+//! Code shared by the auxiliary crates of the chapters
+//!
+//! `cycles` extends the DWT cycle counter to 64 bits and `profile` times code sections with it.
+//! `delay` sleeps on SysTick and `idle` is what the `async` code passes to the executor.
+//!
+//! The auxiliary crates use different versions of the HAL, so nothing here takes HAL types;
+//! clock frequencies are passed in Hz.
+
+#![no_std]
+
+pub mod cycles;
+pub mod delay;
+pub mod profile;
+
+/// Sleeps until the next interrupt, unless an `async` task is ready to run. This is the `idle`
+/// function to pass to `executor::run`
+pub fn idle() {
+    // An interrupt that fires after the check still ends WFI, it is handled right after
+    cortex_m::interrupt::free(|_| {
+        if !executor::is_ready() {
+            cortex_m::asm::wfi();
+        }
+    });
+}
@@ -0,0 +1,188 @@
+//! Cycle accurate profiling of code sections
+//!
+//! Every run of a section wrapped in `profile!` is timed with the 64 bit cycle count of `cycles`
+//! (which has to be started first, e.g. by `MonoTimer::new`):
+//!
+//! ``` ignore
+//! let whoami = profile!("who_am_i", who_am_i(i2c1));
+//!
+//! // Or time everything up to the end of the scope
+//! let _profile = profile!("main loop");
+//! ```
+//!
+//! Each section keeps its number of runs and its min / max / mean cycle count, `report` prints
+//! them as a table. A section is one `profile!` call, two calls with the same name are listed
+//! separately. Time spent sleeping in WFI is not counted, see `cycles`.
+
+use core::{
+    cell::{Cell, RefCell},
+    fmt::{self, Write},
+};
+
+use cortex_m::{
+    interrupt::{self, CriticalSection, Mutex},
+    itm,
+    peripheral::itm::Stim,
+};
+
+use crate::cycles;
+
+/// Maximum number of sections that `report` can list, the ones run after that are not recorded
+pub const MAX_SECTIONS: usize = 16;
+
+#[derive(Clone, Copy)]
+struct Stats {
+    runs: u32,
+    min: u64,
+    max: u64,
+    total: u64,
+}
+
+const NO_STATS: Stats = Stats {
+    runs: 0,
+    min: u64::MAX,
+    max: 0,
+    total: 0,
+};
+
+// Sections that have been run at least once since boot (or the last `reset`)
+static SECTIONS: Mutex<RefCell<[Option<&'static Section>; MAX_SECTIONS]>> =
+    Mutex::new(RefCell::new([None; MAX_SECTIONS]));
+
+/// Statistics of a code section, created by `profile!`
+pub struct Section {
+    name: &'static str,
+    stats: Mutex<Cell<Stats>>,
+}
+
+impl Section {
+    #[doc(hidden)]
+    pub const fn new(name: &'static str) -> Self {
+        Section {
+            name,
+            stats: Mutex::new(Cell::new(NO_STATS)),
+        }
+    }
+
+    /// Starts a run of the section, it ends when the returned guard is dropped
+    pub fn enter(&'static self) -> Guard {
+        Guard {
+            section: self,
+            start: cycles::now(),
+        }
+    }
+
+    fn record(&'static self, cycles: u64) {
+        interrupt::free(|cs| {
+            let mut stats = self.stats.borrow(cs).get();
+            if stats.runs == 0 && !register(self, cs) {
+                return;
+            }
+
+            stats.runs = stats.runs.saturating_add(1);
+            stats.min = stats.min.min(cycles);
+            stats.max = stats.max.max(cycles);
+            stats.total = stats.total.saturating_add(cycles);
+            self.stats.borrow(cs).set(stats);
+        })
+    }
+}
+
+// Adds `section` to the table, returns `false` if the table is full
+fn register(section: &'static Section, cs: &CriticalSection) -> bool {
+    let mut sections = SECTIONS.borrow(cs).borrow_mut();
+    match sections.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => {
+            *slot = Some(section);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Measures a run of a section, see `Section::enter`
+pub struct Guard {
+    section: &'static Section,
+    start: u64,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.section.record(cycles::now() - self.start);
+    }
+}
+
+/// Times a code section, `profile!(name, expr)` evaluates `expr` and times that, `profile!(name)`
+/// returns a guard that times everything up to the point where it is dropped
+#[macro_export]
+macro_rules! profile {
+    ($name:expr) => {{
+        static SECTION: $crate::profile::Section = $crate::profile::Section::new($name);
+        SECTION.enter()
+    }};
+    ($name:expr, $body:expr) => {{
+        let _guard = $crate::profile!($name);
+        $body
+    }};
+}
+
+/// Prints the statistics of every section as a table, with the cycle counts converted to µs
+///
+/// `frequency` is the core clock frequency in Hz, e.g. `mono_timer.frequency().0`
+pub fn report<W: Write>(out: &mut W, frequency: u32) -> fmt::Result {
+    writeln!(
+        out,
+        "{:<24} {:>8} {:>10} {:>10} {:>10}",
+        "section", "runs", "min µs", "max µs", "mean µs"
+    )?;
+
+    // One snapshot of the table and the statistics, a `reset` from an interrupt handler can't
+    // come in between and leave a listed section with no runs
+    let sections = interrupt::free(|cs| {
+        SECTIONS
+            .borrow(cs)
+            .borrow()
+            .map(|slot| slot.map(|section| (section.name, section.stats.borrow(cs).get())))
+    });
+    let micros = |cycles: u64| cycles as f32 * 1_000_000.0 / frequency as f32;
+
+    for (name, stats) in sections.iter().flatten() {
+        let mean = stats.total / u64::from(stats.runs);
+
+        writeln!(
+            out,
+            "{:<24} {:>8} {:>10.3} {:>10.3} {:>10.3}",
+            name,
+            stats.runs,
+            micros(stats.min),
+            micros(stats.max),
+            micros(mean)
+        )?;
+    }
+
+    Ok(())
+}
+
+/// `report`, sent over an ITM stimulus port
+pub fn report_itm(stim: &mut Stim, frequency: u32) {
+    report(&mut ItmWriter(stim), frequency).ok();
+}
+
+/// Forgets all statistics
+pub fn reset() {
+    interrupt::free(|cs| {
+        let mut sections = SECTIONS.borrow(cs).borrow_mut();
+        for section in sections.iter_mut().filter_map(|slot| slot.take()) {
+            section.stats.borrow(cs).set(NO_STATS);
+        }
+    })
+}
+
+struct ItmWriter<'a>(&'a mut Stim);
+
+impl Write for ItmWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        itm::write_str(self.0, s);
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+//! Clock profiles shared by the aux crates
+//!
+//! Each aux crate's `init` takes a `ClockProfile` and hands it to the HAL's `CFGR` before
+//! freezing the clocks. Everything that is computed from the frozen `Clocks` (timer prescalers,
+//! USART baud rate, I2C timing, MonoTimer frequency, delays) then follows the profile:
+//!
+//! ``` ignore
+//! let (leds, timers) = aux9::init(ClockProfile::PLL_72MHZ);
+//! ```
+//!
+//! The HAL picks the closest configuration it can, `init` then panics with `check` if that is
+//! not exactly the profile.
+//!
+//! `timer_clock` and `period` turn the frozen clocks into timer settings.
+//!
+//! The ITM output is clocked by the core too, change the last argument of `tpiu config` in
+//! `openocd.gdb` to `sysclk` when using anything faster than 8 MHz.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod period;
+
+use core::fmt;
+
+/// Frequency of HSE on the STM32F3DISCOVERY: the 8 MHz MCO output of the ST-LINK, which is a
+/// clock signal and not a crystal, so the oscillator has to be bypassed
+pub const HSE_FREQUENCY: u32 = 8_000_000;
+
+/// Where SYSCLK comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// The internal 8 MHz RC oscillator
+    Hsi,
+    /// The ST-LINK MCO, see `HSE_FREQUENCY`
+    Hse,
+    /// The PLL, fed by HSE
+    PllHse,
+}
+
+/// A complete clock configuration, all frequencies in Hz
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClockProfile {
+    pub name: &'static str,
+    pub source: Source,
+    pub sysclk: u32,
+    /// AHB, the core, DMA and GPIO clock
+    pub hclk: u32,
+    /// APB1: TIM2-7, I2C, USART2-5
+    pub pclk1: u32,
+    /// APB2: TIM1, USART1
+    pub pclk2: u32,
+}
+
+impl ClockProfile {
+    /// The reset configuration
+    pub const HSI_8MHZ: ClockProfile = ClockProfile {
+        name: "HSI 8 MHz",
+        source: Source::Hsi,
+        sysclk: 8_000_000,
+        hclk: 8_000_000,
+        pclk1: 8_000_000,
+        pclk2: 8_000_000,
+    };
+
+    /// Same speed as `HSI_8MHZ`, but as accurate as the ST-LINK crystal
+    pub const HSE_8MHZ: ClockProfile = ClockProfile {
+        name: "HSE 8 MHz",
+        source: Source::Hse,
+        sysclk: HSE_FREQUENCY,
+        hclk: HSE_FREQUENCY,
+        pclk1: HSE_FREQUENCY,
+        pclk2: HSE_FREQUENCY,
+    };
+
+    pub const PLL_48MHZ: ClockProfile = ClockProfile {
+        name: "PLL 48 MHz",
+        source: Source::PllHse,
+        sysclk: 48_000_000,
+        hclk: 48_000_000,
+        pclk1: 24_000_000,
+        pclk2: 48_000_000,
+    };
+
+    pub const PLL_64MHZ: ClockProfile = ClockProfile {
+        name: "PLL 64 MHz",
+        source: Source::PllHse,
+        sysclk: 64_000_000,
+        hclk: 64_000_000,
+        pclk1: 32_000_000,
+        pclk2: 64_000_000,
+    };
+
+    /// The fastest the chip goes
+    pub const PLL_72MHZ: ClockProfile = ClockProfile {
+        name: "PLL 72 MHz",
+        source: Source::PllHse,
+        sysclk: 72_000_000,
+        hclk: 72_000_000,
+        pclk1: 36_000_000,
+        pclk2: 72_000_000,
+    };
+
+    /// Returns `true` if the profile needs HSE (i.e. the ST-LINK MCO)
+    pub fn uses_hse(&self) -> bool {
+        self.source != Source::Hsi
+    }
+
+    /// Compares the profile with the frozen clocks, in Hz, and returns the first one that is off
+    pub fn check(&self, sysclk: u32, hclk: u32, pclk1: u32, pclk2: u32) -> Result<(), Mismatch> {
+        let clocks = [
+            ("SYSCLK", self.sysclk, sysclk),
+            ("HCLK", self.hclk, hclk),
+            ("PCLK1", self.pclk1, pclk1),
+            ("PCLK2", self.pclk2, pclk2),
+        ];
+
+        for (clock, expected, actual) in clocks {
+            if actual != expected {
+                return Err(Mismatch {
+                    profile: self.name,
+                    clock,
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for ClockProfile {
+    fn default() -> Self {
+        ClockProfile::HSI_8MHZ
+    }
+}
+
+/// A clock of the profile that the HAL didn't configure, see `ClockProfile::check`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub profile: &'static str,
+    pub clock: &'static str,
+    pub expected: u32,
+    pub actual: u32,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} not reachable: {} is {} Hz instead of {} Hz",
+            self.profile, self.clock, self.actual, self.expected
+        )
+    }
+}
+
+/// Clock of the timers on an APB bus, given its clock and prescaler (PPRE1 / PPRE2 as a
+/// divider): twice the bus clock when the bus is divided
+pub fn timer_clock(pclk: u32, ppre: u8) -> u32 {
+    match ppre {
+        1 => pclk,
+        _ => 2 * pclk,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_matching_clocks() {
+        let profile = ClockProfile::PLL_72MHZ;
+
+        assert_eq!(
+            profile.check(72_000_000, 72_000_000, 36_000_000, 72_000_000),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn check_reports_the_first_mismatch() {
+        let profile = ClockProfile::PLL_48MHZ;
+        let mismatch = profile
+            .check(48_000_000, 48_000_000, 48_000_000, 24_000_000)
+            .unwrap_err();
+
+        assert_eq!(mismatch.clock, "PCLK1");
+        assert_eq!(mismatch.expected, 24_000_000);
+        assert_eq!(mismatch.actual, 48_000_000);
+        assert_eq!(
+            mismatch.to_string(),
+            "PLL 48 MHz not reachable: PCLK1 is 48000000 Hz instead of 24000000 Hz"
+        );
+    }
+
+    #[test]
+    fn timer_clock_doubles_when_divided() {
+        assert_eq!(timer_clock(8_000_000, 1), 8_000_000);
+        assert_eq!(timer_clock(36_000_000, 2), 72_000_000);
+        assert_eq!(timer_clock(500_000, 16), 1_000_000);
+    }
+}
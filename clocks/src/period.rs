@@ -0,0 +1,164 @@
+//! Timer periods as prescaler and auto-reload values
+//!
+//! A timer counts ticks of its kernel clock (see `timer_clock`), divided by PSC + 1, and
+//! overflows after ARR + 1 of those. `prescale` picks both for a period, `max_arr` is the largest
+//! ARR of the timer: 0xFFFF for a 16-bit counter, 0xFFFF_FFFF for TIM2:
+//!
+//! ``` ignore
+//! let (psc, arr) = prescale(ticks(timer_clock, Duration::from_millis(50)), 0xFFFF)?;
+//! ```
+
+use core::{fmt, time::Duration};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+// PSC is 16 bits wide on all the timers
+const MAX_PRESCALER: u64 = 1 << 16;
+
+/// The requested period can't be represented with this timer and clock configuration
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Shorter than two ticks of the timer clock
+    PeriodTooShort,
+    /// Longer than the counter can count with the largest prescaler
+    PeriodTooLong,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Error::PeriodTooShort => "period too short for the timer clock",
+            Error::PeriodTooLong => "period too long for the timer",
+        })
+    }
+}
+
+/// Number of ticks of `clock` (in Hz) in `period`
+pub fn ticks(clock: u32, period: Duration) -> u64 {
+    let ticks = u128::from(clock) * period.as_nanos() / u128::from(NANOS_PER_SEC);
+
+    ticks.min(u128::from(u64::MAX)) as u64
+}
+
+/// Longest period a timer with an ARR of at most `max_arr` can count, in ticks
+pub fn max_ticks(max_arr: u32) -> u64 {
+    MAX_PRESCALER * (u64::from(max_arr) + 1)
+}
+
+/// Splits a period of `ticks` into a prescaler and an auto-reload value, both as they are written
+/// to PSC and ARR. The smallest prescaler that works is picked, for the best resolution
+pub fn prescale(ticks: u64, max_arr: u32) -> Result<(u16, u32), Error> {
+    // With ARR = 0 the counter never overflows
+    if ticks < 2 {
+        return Err(Error::PeriodTooShort);
+    }
+
+    if ticks > max_ticks(max_arr) {
+        return Err(Error::PeriodTooLong);
+    }
+
+    let max_reload = u64::from(max_arr) + 1;
+    let prescaler = ticks.div_ceil(max_reload);
+
+    // Rounded to the nearest tick of the prescaled clock
+    let reload = (ticks + prescaler / 2) / prescaler;
+
+    Ok(((prescaler - 1) as u16, (reload.max(2) - 1) as u32))
+}
+
+/// Splits a delay of `ticks` into periods the timer can count: as many of the longest period as
+/// needed (which `prescale` represents exactly), then the rest
+pub fn chunks(mut ticks: u64, max_arr: u32) -> impl Iterator<Item = u64> {
+    let max = max_ticks(max_arr);
+
+    core::iter::from_fn(move || {
+        let chunk = ticks.min(max);
+        ticks -= chunk;
+        (chunk != 0).then_some(chunk)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timer_clock;
+
+    const MAX_ARR_16: u32 = 0xFFFF;
+    const MAX_ARR_32: u32 = 0xFFFF_FFFF;
+
+    #[test]
+    fn one_microsecond() {
+        assert_eq!(ticks(8_000_000, Duration::from_micros(1)), 8);
+        assert_eq!(prescale(8, MAX_ARR_16), Ok((0, 7)));
+
+        // A single tick of a 1 MHz clock is too short
+        assert_eq!(ticks(1_000_000, Duration::from_micros(1)), 1);
+        assert_eq!(prescale(1, MAX_ARR_16), Err(Error::PeriodTooShort));
+        assert_eq!(prescale(0, MAX_ARR_16), Err(Error::PeriodTooShort));
+    }
+
+    #[test]
+    fn one_millisecond() {
+        assert_eq!(ticks(8_000_000, Duration::from_millis(1)), 8_000);
+        assert_eq!(prescale(8_000, MAX_ARR_16), Ok((0, 7_999)));
+    }
+
+    #[test]
+    fn above_16_bits() {
+        // 72 MHz: 72000 ticks don't fit in ARR, the clock is divided by 2
+        assert_eq!(ticks(72_000_000, Duration::from_millis(1)), 72_000);
+        assert_eq!(prescale(72_000, MAX_ARR_16), Ok((1, 35_999)));
+        // TIM2 counts them directly
+        assert_eq!(prescale(72_000, MAX_ARR_32), Ok((0, 71_999)));
+
+        // Exactly the 16-bit range, and one more
+        assert_eq!(prescale(65_536, MAX_ARR_16), Ok((0, 65_535)));
+        assert_eq!(prescale(65_537, MAX_ARR_16), Ok((1, 32_768)));
+
+        // One second at 72 MHz, rounded to the nearest prescaled tick
+        assert_eq!(prescale(72_000_000, MAX_ARR_16), Ok((1_098, 65_513)));
+    }
+
+    #[test]
+    fn too_long() {
+        assert_eq!(max_ticks(MAX_ARR_16), 1 << 32);
+        assert_eq!(prescale(1 << 32, MAX_ARR_16), Ok((0xFFFF, 0xFFFF)));
+        assert_eq!(
+            prescale((1 << 32) + 1, MAX_ARR_16),
+            Err(Error::PeriodTooLong)
+        );
+        assert_eq!(prescale(u64::MAX, MAX_ARR_32), Err(Error::PeriodTooLong));
+    }
+
+    #[test]
+    fn doubled_timer_clock() {
+        // PCLK1 = 36 MHz divided from 72 MHz, the timers on APB1 still run at 72 MHz
+        let clock = timer_clock(36_000_000, 2);
+
+        assert_eq!(ticks(clock, Duration::from_millis(1)), 72_000);
+        assert_eq!(
+            prescale(ticks(clock, Duration::from_micros(10)), MAX_ARR_16),
+            Ok((0, 719))
+        );
+    }
+
+    #[test]
+    fn ticks_saturate() {
+        assert_eq!(ticks(72_000_000, Duration::MAX), u64::MAX);
+        assert_eq!(ticks(8_000_000, Duration::from_nanos(100)), 0);
+    }
+
+    #[test]
+    fn chunks_of_long_delays() {
+        let max = max_ticks(MAX_ARR_16);
+
+        assert_eq!(chunks(0, MAX_ARR_16).count(), 0);
+        assert!(chunks(100_000, MAX_ARR_16).eq([100_000]));
+        assert!(chunks(max, MAX_ARR_16).eq([max]));
+        assert!(chunks(2 * max + 5, MAX_ARR_16).eq([max, max, 5]));
+
+        // Every chunk but a short last one can be prescaled
+        for chunk in chunks(3 * max + 1_000, MAX_ARR_16) {
+            assert!(prescale(chunk, MAX_ARR_16).is_ok());
+        }
+    }
+}
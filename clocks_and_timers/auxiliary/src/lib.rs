@@ -5,25 +5,51 @@
 #[allow(unused_extern_crates)] // NOTE(allow) rust-lang/rust#53964
 extern crate panic_itm; // panic handler
 
-pub use cortex_m::asm::{bkpt, nop};
+pub use cortex_m::{
+    asm::{bkpt, nop},
+    peripheral::NVIC,
+};
+pub use aux_common::idle;
+pub use clocks::ClockProfile;
+pub use compass_leds::{CompassLeds, Direction};
 pub use cortex_m_rt::entry;
-pub use stm32f3::stm32f303::{rcc, tim6, RCC, TIM6};
-pub use stm32f3_discovery::switch_hal;
+pub use nb::block;
+pub use stm32f3::stm32f303::{interrupt, Interrupt};
+pub use stm32f3_discovery::{
+    stm32f3xx_hal::{
+        hal::blocking::delay::DelayMs,
+        stm32::{TIM2, TIM6, TIM7},
+    },
+    switch_hal,
+};
+
+pub mod timer;
 
 use stm32f3_discovery::{
     leds::Leds,
-    stm32f3xx_hal::{prelude::*, stm32},
+    stm32f3xx_hal::{prelude::*, rcc::CFGR, stm32},
 };
+use clocks::HSE_FREQUENCY;
+use timer::Timer;
+
+/// The timers that `timer::Timer` drives, powered on and stopped
+pub struct Timers {
+    pub tim6: Timer<TIM6>,
+    pub tim7: Timer<TIM7>,
+    pub tim2: Timer<TIM2>,
+}
 
-pub fn init() -> (
-    Leds,
-    &'static rcc::RegisterBlock,
-    &'static tim6::RegisterBlock,
-) {
+pub fn init(profile: ClockProfile) -> (Leds, Timers) {
     let p = stm32::Peripherals::take().unwrap();
 
+    let mut flash = p.FLASH.constrain();
     let mut rcc = p.RCC.constrain();
 
+    let clocks = configure_clocks(rcc.cfgr, &profile).freeze(&mut flash.acr);
+    profile
+        .check(clocks.sysclk().0, clocks.hclk().0, clocks.pclk1().0, clocks.pclk2().0)
+        .unwrap_or_else(|mismatch| panic!("{}", mismatch));
+
     let mut gpioe = p.GPIOE.split(&mut rcc.ahb);
 
     let leds = Leds::new(
@@ -39,5 +65,26 @@ pub fn init() -> (
         &mut gpioe.otyper,
     );
 
-    (leds, unsafe { &*RCC::ptr() }, unsafe { &*TIM6::ptr() })
-}
\ No newline at end of file
+    let timers = Timers {
+        tim6: Timer::tim6(p.TIM6, clocks),
+        tim7: Timer::tim7(p.TIM7, clocks),
+        tim2: Timer::tim2(p.TIM2, clocks),
+    };
+
+    (leds, timers)
+}
+
+// Hands `profile` to the HAL, which picks the PLL multiplier, the prescalers and the flash wait
+// states
+fn configure_clocks(cfgr: CFGR, profile: &ClockProfile) -> CFGR {
+    let cfgr = if profile.uses_hse() {
+        cfgr.use_hse(HSE_FREQUENCY.hz()).bypass_hse()
+    } else {
+        cfgr
+    };
+
+    cfgr.sysclk(profile.sysclk.hz())
+        .hclk(profile.hclk.hz())
+        .pclk1(profile.pclk1.hz())
+        .pclk2(profile.pclk2.hz())
+}
@@ -0,0 +1,268 @@
+//! Typed driver for the basic timers TIM6 / TIM7 and the general purpose TIM2
+//!
+//! The prescaler and the auto-reload value are computed from the frozen `Clocks`, so a period
+//! is asked for as a `Duration` instead of a number of ticks:
+//!
+//! ``` ignore
+//! let mut timer = Timer::tim6(p.TIM6, clocks);
+//! timer.start(Duration::from_millis(50), Mode::OneShot)?;
+//! block!(timer.wait()).ok();
+//! ```
+//!
+//! To sleep until the period is over instead of polling `wait`, call `listen` (or use `delay` and
+//! the `DelayMs` / `DelayUs` implementations, which sleep with WFI) and forward the update
+//! interrupt of the timer to `on_interrupt`:
+//!
+//! ``` ignore
+//! #[interrupt]
+//! fn TIM6_DACUNDER() {
+//!     Timer::<TIM6>::on_interrupt();
+//! }
+//! ```
+
+use core::{convert::Infallible, time::Duration};
+
+use clocks::{
+    period::{chunks, prescale, ticks},
+    timer_clock,
+};
+use cortex_m::{asm, interrupt, peripheral::NVIC};
+use executor::Signal;
+use stm32f3_discovery::stm32f3xx_hal::{
+    hal::blocking::delay::{DelayMs, DelayUs},
+    rcc::Clocks,
+    stm32::{Interrupt, RCC, TIM2, TIM6, TIM7},
+};
+
+pub use clocks::period::Error;
+
+/// What the timer does once a period is over
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Stop, `start` has to be called again (OPM)
+    OneShot,
+    /// Start the next period right away
+    Periodic,
+}
+
+/// A timer that counts periods of a requested length
+pub struct Timer<TIM> {
+    tim: TIM,
+    // Frequency of the timer kernel clock, in Hz
+    clock: u32,
+    // Set by `listen`
+    listening: bool,
+}
+
+macro_rules! timers {
+    ($($TIM:ident: ($tim:ident, $timen:ident, $INTERRUPT:ident, $UPDATE:ident, $max_arr:expr),)+) => {
+        $(
+            // Woken by `on_interrupt`
+            static $UPDATE: Signal = Signal::new();
+
+            impl Timer<$TIM> {
+                /// Powers the timer on, it is left stopped
+                pub fn $tim(tim: $TIM, clocks: Clocks) -> Self {
+                    interrupt::free(|_| {
+                        // NOTE(unsafe) inside a critical section, and we only set our own bit
+                        let rcc = unsafe { &*RCC::ptr() };
+                        rcc.apb1enr.modify(|_, w| w.$timen().set_bit());
+                    });
+
+                    // URS: Writing UG (done by `start`) must not look like the end of a period
+                    tim.cr1.write(|w| w.urs().set_bit());
+
+                    Timer {
+                        tim,
+                        // All three are on APB1
+                        clock: timer_clock(clocks.pclk1().0, clocks.ppre1()),
+                        listening: false,
+                    }
+                }
+
+                /// Frequency of the clock the timer counts, before the prescaler, in Hz
+                pub fn clock(&self) -> u32 {
+                    self.clock
+                }
+
+                /// Starts counting a new period, this restarts a timer that was already running
+                pub fn start(&mut self, period: Duration, mode: Mode) -> Result<(), Error> {
+                    self.start_ticks(ticks(self.clock, period), mode)
+                }
+
+                fn start_ticks(&mut self, ticks: u64, mode: Mode) -> Result<(), Error> {
+                    let (psc, arr) = prescale(ticks, $max_arr)?;
+
+                    self.stop();
+
+                    self.tim.psc.write(|w| w.psc().bits(psc));
+                    // NOTE(unsafe) `prescale` keeps `arr` within the counter width
+                    self.tim.arr.write(|w| unsafe { w.bits(arr) });
+                    // UG: Load PSC (which is buffered) and reset the counter now
+                    self.tim.egr.write(|w| w.ug().set_bit());
+                    self.tim.sr.modify(|_, w| w.uif().clear_bit());
+
+                    // OPM: Clear CEN at the end of the period, CEN: start counting
+                    self.tim.cr1.modify(|_, w| {
+                        w.opm().bit(mode == Mode::OneShot);
+                        w.cen().set_bit()
+                    });
+
+                    Ok(())
+                }
+
+                /// Stops the timer, the period that was being counted never ends
+                pub fn stop(&mut self) {
+                    self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+                }
+
+                /// Returns `true` while a period is being counted
+                pub fn is_running(&self) -> bool {
+                    self.tim.cr1.read().cen().bit_is_set()
+                }
+
+                /// Returns `Ok` once per period, when a period has ended since the last call
+                pub fn wait(&mut self) -> nb::Result<(), Infallible> {
+                    if self.tim.sr.read().uif().bit_is_clear() {
+                        return Err(nb::Error::WouldBlock);
+                    }
+
+                    self.tim.sr.modify(|_, w| w.uif().clear_bit());
+                    if self.listening {
+                        // `on_interrupt` masked it
+                        self.tim.dier.modify(|_, w| w.uie().set_bit());
+                    }
+                    Ok(())
+                }
+
+                /// `wait` for `async` code, needs `listen`
+                pub async fn wait_async(&mut self) {
+                    $UPDATE.wait(|| self.wait().ok()).await
+                }
+
+                /// Fires the update interrupt at the end of every period
+                pub fn listen(&mut self) {
+                    self.listening = true;
+                    self.tim.dier.modify(|_, w| w.uie().set_bit());
+                    // NOTE(unsafe) the handler only touches this timer
+                    unsafe { NVIC::unmask(Interrupt::$INTERRUPT) };
+                }
+
+                /// Stops firing the update interrupt
+                pub fn unlisten(&mut self) {
+                    NVIC::mask(Interrupt::$INTERRUPT);
+                    self.tim.dier.modify(|_, w| w.uie().clear_bit());
+                    self.listening = false;
+                }
+
+                /// Must be called from the interrupt handler of the timer
+                ///
+                /// UIF is left set for `wait` to see, so the interrupt is masked until the next
+                /// `wait` that finds it set
+                pub fn on_interrupt() {
+                    // NOTE(unsafe) only DIER is touched, `wait` doesn't use it
+                    let tim = unsafe { &*$TIM::ptr() };
+                    if tim.sr.read().uif().bit_is_set() {
+                        tim.dier.modify(|_, w| w.uie().clear_bit());
+                    }
+
+                    $UPDATE.wake();
+                }
+
+                /// Sleeps (WFI) for `duration`, the update interrupt must be forwarded to
+                /// `on_interrupt`
+                ///
+                /// Any `duration` works: one that is too long for the timer is counted in several
+                /// periods, and the prescaler of each one is picked for the best resolution. A
+                /// `duration` shorter than two ticks of the timer clock returns right away
+                pub fn delay(&mut self, duration: Duration) {
+                    let listening = self.listening;
+                    self.listen();
+
+                    for chunk in chunks(ticks(self.clock, duration), $max_arr) {
+                        if self.start_ticks(chunk, Mode::OneShot).is_err() {
+                            // Less than two timer ticks, it is over already
+                            continue;
+                        }
+
+                        loop {
+                            // The update interrupt can't fire between the check and WFI, and if it
+                            // fires later it still ends WFI
+                            interrupt::free(|_| {
+                                if self.tim.sr.read().uif().bit_is_clear() {
+                                    asm::wfi();
+                                }
+                            });
+
+                            if self.wait().is_ok() {
+                                break;
+                            }
+                        }
+                    }
+
+                    if !listening {
+                        self.unlisten();
+                    }
+                }
+
+                /// `delay` for `async` code, needs `listen`
+                pub async fn delay_async(&mut self, duration: Duration) {
+                    for chunk in chunks(ticks(self.clock, duration), $max_arr) {
+                        if self.start_ticks(chunk, Mode::OneShot).is_ok() {
+                            self.wait_async().await;
+                        }
+                    }
+                }
+
+                /// Stops the timer and gives it back
+                pub fn free(mut self) -> $TIM {
+                    self.unlisten();
+                    self.stop();
+                    self.tim
+                }
+            }
+
+            impl DelayUs<u32> for Timer<$TIM> {
+                fn delay_us(&mut self, us: u32) {
+                    self.delay(Duration::from_micros(us.into()));
+                }
+            }
+
+            impl DelayUs<u16> for Timer<$TIM> {
+                fn delay_us(&mut self, us: u16) {
+                    self.delay(Duration::from_micros(us.into()));
+                }
+            }
+
+            impl DelayUs<u8> for Timer<$TIM> {
+                fn delay_us(&mut self, us: u8) {
+                    self.delay(Duration::from_micros(us.into()));
+                }
+            }
+
+            impl DelayMs<u32> for Timer<$TIM> {
+                fn delay_ms(&mut self, ms: u32) {
+                    self.delay(Duration::from_millis(ms.into()));
+                }
+            }
+
+            impl DelayMs<u16> for Timer<$TIM> {
+                fn delay_ms(&mut self, ms: u16) {
+                    self.delay(Duration::from_millis(ms.into()));
+                }
+            }
+
+            impl DelayMs<u8> for Timer<$TIM> {
+                fn delay_ms(&mut self, ms: u8) {
+                    self.delay(Duration::from_millis(ms.into()));
+                }
+            }
+        )+
+    }
+}
+
+timers! {
+    TIM6: (tim6, tim6en, TIM6_DACUNDER, TIM6_UPDATE, 0xFFFF),
+    TIM7: (tim7, tim7en, TIM7, TIM7_UPDATE, 0xFFFF),
+    TIM2: (tim2, tim2en, TIM2, TIM2_UPDATE, 0xFFFF_FFFF),
+}
@@ -1,50 +1,54 @@
 #![no_main]
 #![no_std]
 
-use aux9::{entry, switch_hal::OutputSwitch, tim6};
+use core::time::Duration;
 
+use aux9::{
+    entry, interrupt, switch_hal::OutputSwitch, timer::Timer, ClockProfile, CompassLeds, DelayMs,
+    Direction, Timers, TIM6,
+};
+
+// Blocking version of `delay_async`, kept for comparison. It doesn't spin on UIF either, the
+// core sleeps (WFI) until the update interrupt
+#[allow(dead_code)]
 #[inline(never)]
-fn delay(tim6: &tim6::RegisterBlock, ms: u16) {
-    // Set the timer to go off in `ms` ticks
-    // 1 tick = 1ms
-    tim6.arr.write(|w| w.arr().bits(ms));
-    // CEN: Enable the counter
-    tim6.cr1.write(|w| w.cen().set_bit());
-    // Wait until the alarm goes off (until the update event occurs)
-    while !tim6.sr.read().uif().bit_is_set() {}
-    // Clear the update event flag (or next time we enter this function it will think the update event has already
-    // happend and skip over the busy waiting part)
-    tim6.sr.write(|w| w.uif().clear_bit());
+fn delay(tim6: &mut Timer<TIM6>, ms: u32) {
+    tim6.delay_ms(ms);
+}
+
+// Same as `delay`, but other `async` tasks can run in the meantime
+async fn delay_async(tim6: &mut Timer<TIM6>, ms: u32) {
+    // The prescaler is picked from the APB1 clock, no need to assume it runs at 8 MHz, and
+    // delays that don't fit in one period of the timer are split
+    tim6.delay_async(Duration::from_millis(ms.into())).await;
+}
+
+// One lap of the roulette
+async fn lap(leds: &mut [impl OutputSwitch; 8], tim6: &mut Timer<TIM6>, ms: u32) {
+    for curr in Direction::ALL {
+        leds.get_mut(curr.clockwise()).on().ok();
+        delay_async(tim6, ms).await;
+        leds.get_mut(curr).off().ok();
+        delay_async(tim6, ms).await;
+    }
 }
 
 #[entry]
 fn main() -> ! {
-    let (leds, rcc, tim6) = aux9::init();
+    // APB1 is divided by 2 at 72 MHz, so TIM6 counts at twice PCLK1
+    let (leds, Timers { mut tim6, .. }) = aux9::init(ClockProfile::PLL_72MHZ);
     let mut leds = leds.into_array();
 
-    // Power on the TIM6 timer
-    rcc.apb1enr.write(|w| w.tim6en().set_bit());
-
-    // OPM select one pulse mode
-    // CEN keep the counter disabled for now
-    tim6.cr1.write(|w| w.opm().set_bit().cen().clear_bit());
-
-    // Configure the prescaler to have the counter operate at 1 Khz
-    // APB1_CLOCK = 8 MHz
-    // PSC = 7999
-    // 8 Mhz / (7999 + 1) = 1KHz
-    // The counter (CNT) will increase on every millisecond
-    tim6.psc.write(|w| w.psc().bits(7999));
+    // UIE: Interrupt on the update event, for `delay_async`
+    tim6.listen();
 
     let ms = 50;
     loop {
-        for curr in 0..8 {
-            let next = (curr + 1) % 8;
-
-            leds[next].on().unwrap();
-            delay(tim6, ms);
-            leds[curr].off().unwrap();
-            delay(tim6, ms);
-        }
+        executor::block_on(lap(&mut leds, &mut tim6, ms), aux9::idle);
     }
-}
\ No newline at end of file
+}
+
+#[interrupt]
+fn TIM6_DACUNDER() {
+    Timer::<TIM6>::on_interrupt();
+}
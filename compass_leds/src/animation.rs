@@ -0,0 +1,524 @@
+//! Non-blocking LED animations
+//!
+//! An `Animation` turns the current time into a `Frame`, the brightness of every LED. Nothing
+//! blocks, the main loop (or a timer task) calls `tick` as often as it likes and shows the
+//! frame:
+//!
+//! ``` ignore
+//! let mut animation = Animation::new(Pattern::Chase { step: 50, tail: 2 });
+//!
+//! loop {
+//!     leds.show(&animation.tick(now()));
+//!     // Anything else the firmware has to do
+//! }
+//! ```
+//!
+//! Times are in milliseconds, measured from whatever point the caller likes. The pattern, speed
+//! and direction can be changed at any time, the animation carries on from where it was.
+
+use heapless::Vec;
+
+use crate::Direction;
+
+/// Brightness of each LED, in `Direction::ALL` order: 0 is off, 255 fully on
+pub type Frame = [u8; 8];
+
+/// Every LED off
+pub const OFF: Frame = [0; 8];
+
+/// Maximum number of keyframes in a `Keyframes` table
+pub const MAX_KEYFRAMES: usize = 32;
+
+/// Speed of an animation that runs as designed, see `Animation::set_speed`
+pub const NORMAL_SPEED: u16 = 100;
+
+/// A step of a `Keyframes` table
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Keyframe {
+    pub frame: Frame,
+    /// How long `frame` is shown, in ms
+    pub duration: u32,
+}
+
+/// A `Keyframes` table already holds `MAX_KEYFRAMES` frames
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TableFull;
+
+/// A custom animation: frames shown one after the other
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keyframes {
+    frames: Vec<Keyframe, MAX_KEYFRAMES>,
+    // Number of times the table is played, `None` loops forever
+    repeat: Option<u32>,
+}
+
+impl Default for Keyframes {
+    /// An empty table, see `push`
+    fn default() -> Self {
+        Keyframes {
+            frames: Vec::new(),
+            repeat: None,
+        }
+    }
+}
+
+impl Keyframes {
+    /// Plays `frames` in a loop
+    pub fn new(frames: &[Keyframe]) -> Result<Self, TableFull> {
+        Ok(Keyframes {
+            frames: Vec::from_slice(frames).map_err(|_| TableFull)?,
+            repeat: None,
+        })
+    }
+
+    /// The roulette of the `led_roulette` chapter: each LED alone for 100 ms, then together with
+    /// the next one clockwise for 50 ms
+    pub fn roulette() -> Self {
+        let mut keyframes = Keyframes::default();
+
+        for direction in Direction::ALL {
+            let mut frame = OFF;
+            frame[direction.index()] = 255;
+            keyframes
+                .push(Keyframe {
+                    frame,
+                    duration: 100,
+                })
+                .ok();
+
+            frame[direction.clockwise().index()] = 255;
+            keyframes
+                .push(Keyframe {
+                    frame,
+                    duration: 50,
+                })
+                .ok();
+        }
+
+        keyframes
+    }
+
+    /// Plays the table `times` times and then holds the last frame, or loops forever with `None`
+    pub fn repeat(mut self, times: Option<u32>) -> Self {
+        self.repeat = times;
+        self
+    }
+
+    pub fn push(&mut self, keyframe: Keyframe) -> Result<(), TableFull> {
+        self.frames.push(keyframe).map_err(|_| TableFull)
+    }
+
+    pub fn frames(&self) -> &[Keyframe] {
+        &self.frames
+    }
+
+    /// Length of one run through the table, in ms
+    pub fn duration(&self) -> u64 {
+        self.frames
+            .iter()
+            .map(|keyframe| u64::from(keyframe.duration))
+            .sum()
+    }
+
+    /// Returns `true` once `elapsed` is past the last repetition. A total length that doesn't
+    /// fit in a `u64` is never over
+    fn is_over(&self, elapsed: u64) -> bool {
+        let total = self
+            .repeat
+            .and_then(|times| self.duration().checked_mul(u64::from(times)));
+
+        total.is_some_and(|total| elapsed >= total)
+    }
+
+    fn frame(&self, elapsed: u64) -> Frame {
+        let duration = self.duration();
+        let last = self.frames.last().map_or(OFF, |keyframe| keyframe.frame);
+        if duration == 0 || self.is_over(elapsed) {
+            return last;
+        }
+
+        let mut offset = elapsed % duration;
+        for keyframe in &self.frames {
+            if offset < u64::from(keyframe.duration) {
+                return keyframe.frame;
+            }
+            offset -= u64::from(keyframe.duration);
+        }
+
+        last
+    }
+}
+
+/// What an `Animation` shows. `step` and `period` are in ms, at normal speed
+#[allow(clippy::large_enum_variant)] // NOTE(allow) there is no heap to box the keyframes in
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    /// One LED going around, followed by `tail` LEDs that fade out
+    Chase {
+        step: u32,
+        tail: u8,
+    },
+    /// One LED going around half a turn and back, between N and S
+    Bounce {
+        step: u32,
+    },
+    /// All LEDs fading in and out together, `period` is one full breath
+    Breathe {
+        period: u32,
+    },
+    /// Two opposite LEDs turning like a propeller
+    Spin {
+        step: u32,
+    },
+    /// Random LEDs flashing and fading out; `density` is the chance each LED flashes at each
+    /// step, out of 256
+    Sparkle {
+        step: u32,
+        density: u8,
+    },
+    Keyframes(Keyframes),
+}
+
+impl Pattern {
+    // Length of a lap of the patterns that go around the compass, in ms. Reversing their
+    // direction plays them backwards, the other patterns ignore it
+    fn lap(&self) -> Option<u64> {
+        match self {
+            Pattern::Chase { step, .. } | Pattern::Bounce { step } | Pattern::Spin { step } => {
+                Some(8 * u64::from((*step).max(1)))
+            }
+            _ => None,
+        }
+    }
+
+    // The frame `elapsed` ms into the pattern, `clockwise` only tells which side the chase's tail
+    // is on
+    fn frame(&self, elapsed: u64, clockwise: bool) -> Frame {
+        let mut frame = OFF;
+
+        match self {
+            Pattern::Chase { step, tail } => {
+                let head = steps(elapsed, *step) % 8;
+                let length = u32::from(*tail) + 1;
+
+                // Drawn from the end of the tail, so a tail longer than the circle doesn't cover
+                // the head
+                for behind in (0..length.min(8)).rev() {
+                    let offset = if clockwise { 8 - behind % 8 } else { behind };
+                    let direction = Direction::from_index((head as u32 + offset) as usize);
+                    frame[direction.index()] = (255 * (length - behind) / length) as u8;
+                }
+            }
+            Pattern::Bounce { step } => {
+                // 0, 1, .. 4 (S), then back 3, 2, 1
+                let position = steps(elapsed, *step) % 8;
+                let position = if position <= 4 {
+                    position
+                } else {
+                    8 - position
+                };
+                frame[position as usize] = 255;
+            }
+            Pattern::Breathe { period } => {
+                let period = u64::from((*period).max(2));
+                let position = elapsed % period;
+                let half = period / 2;
+                let level = if position < half {
+                    position * 255 / half
+                } else {
+                    (period - position) * 255 / (period - half)
+                };
+                frame = [level as u8; 8];
+            }
+            Pattern::Spin { step } => {
+                let blade = Direction::from_index((steps(elapsed, *step) % 8) as usize);
+                frame[blade.index()] = 255;
+                frame[blade.opposite().index()] = 255;
+            }
+            Pattern::Sparkle { step, density } => {
+                let step = u64::from((*step).max(1));
+                let n = elapsed / step;
+                // Flashes fade out over the step
+                let level = 255 - (elapsed % step * 255 / step) as u8;
+                for (index, brightness) in frame.iter_mut().enumerate() {
+                    if (hash(n, index) & 0xff) < u32::from(*density) {
+                        *brightness = level;
+                    }
+                }
+            }
+            Pattern::Keyframes(keyframes) => frame = keyframes.frame(elapsed),
+        }
+
+        frame
+    }
+}
+
+// Number of whole steps of `step` ms in `elapsed`
+fn steps(elapsed: u64, step: u32) -> u64 {
+    elapsed / u64::from(step.max(1))
+}
+
+// Pseudo random bits for `Sparkle`, always the same for the same step and LED
+fn hash(step: u64, index: usize) -> u32 {
+    let mut x = (step as u32).wrapping_mul(0x9e37_79b9) ^ (index as u32).wrapping_mul(0x85eb_ca6b);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x2c1b_3c6d);
+    x ^= x >> 12;
+    x
+}
+
+/// A `Pattern` being played
+#[derive(Clone, Debug)]
+pub struct Animation {
+    pattern: Pattern,
+    // Position in the pattern, in hundredths of a ms so that odd speeds don't drift
+    position: i64,
+    speed: u16,
+    clockwise: bool,
+    // Time of the last `tick`
+    last: Option<u64>,
+}
+
+impl Animation {
+    /// Starts `pattern` from the beginning, at normal speed and clockwise
+    pub fn new(pattern: Pattern) -> Self {
+        Animation {
+            pattern,
+            position: 0,
+            speed: NORMAL_SPEED,
+            clockwise: true,
+            last: None,
+        }
+    }
+
+    /// Advances the animation to `now` and returns what the LEDs should show
+    pub fn tick(&mut self, now: u64) -> Frame {
+        let elapsed = now.saturating_sub(self.last.unwrap_or(now));
+        self.last = Some(now);
+
+        let delta = (elapsed as i64).saturating_mul(i64::from(self.speed));
+        self.position = if self.pattern.lap().is_some() && !self.clockwise {
+            self.position.saturating_sub(delta)
+        } else {
+            self.position.saturating_add(delta)
+        };
+
+        self.frame()
+    }
+
+    /// The frame at the last `tick`
+    pub fn frame(&self) -> Frame {
+        let elapsed = self.position.div_euclid(100);
+        let elapsed = match self.pattern.lap() {
+            // Played backwards, a turning pattern wraps around to the end of its lap
+            Some(lap) => elapsed.rem_euclid(lap as i64) as u64,
+            None => elapsed.max(0) as u64,
+        };
+
+        self.pattern.frame(elapsed, self.clockwise)
+    }
+
+    /// Switches to `pattern`, from its beginning
+    pub fn set_pattern(&mut self, pattern: Pattern) {
+        self.pattern = pattern;
+        self.position = 0;
+        // The time up to the next `tick` was spent on the old pattern
+        self.last = None;
+    }
+
+    pub fn pattern(&self) -> &Pattern {
+        &self.pattern
+    }
+
+    /// Sets the speed in percent: `NORMAL_SPEED` (100) plays the pattern as designed, 200 twice
+    /// as fast, 0 freezes it
+    pub fn set_speed(&mut self, speed: u16) {
+        self.speed = speed;
+    }
+
+    pub fn speed(&self) -> u16 {
+        self.speed
+    }
+
+    /// Sets which way chases, bounces and spins turn
+    pub fn set_clockwise(&mut self, clockwise: bool) {
+        self.clockwise = clockwise;
+    }
+
+    pub fn is_clockwise(&self) -> bool {
+        self.clockwise
+    }
+
+    /// Turns the other way
+    pub fn reverse(&mut self) {
+        self.clockwise = !self.clockwise;
+    }
+
+    /// Returns `true` once a `Keyframes` pattern with a repeat count has played to the end
+    pub fn is_finished(&self) -> bool {
+        match &self.pattern {
+            Pattern::Keyframes(keyframes) => keyframes.is_over(self.position.max(0) as u64 / 100),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The frame `elapsed` ms after the start, at normal speed and clockwise
+    fn frame_at(pattern: Pattern, elapsed: u64) -> Frame {
+        let mut animation = Animation::new(pattern);
+        animation.tick(0);
+        animation.tick(elapsed)
+    }
+
+    fn keyframe(led: Direction, duration: u32) -> Keyframe {
+        let mut frame = OFF;
+        frame[led.index()] = 255;
+        Keyframe { frame, duration }
+    }
+
+    #[test]
+    fn chase() {
+        let chase = Pattern::Chase { step: 100, tail: 2 };
+
+        assert_eq!(frame_at(chase.clone(), 0), [255, 0, 0, 0, 0, 0, 85, 170]);
+        assert_eq!(frame_at(chase.clone(), 150), [170, 255, 0, 0, 0, 0, 0, 85]);
+        assert_eq!(frame_at(chase, 800), [255, 0, 0, 0, 0, 0, 85, 170]);
+    }
+
+    #[test]
+    fn chase_tail_longer_than_the_circle() {
+        let frame = frame_at(
+            Pattern::Chase {
+                step: 100,
+                tail: 20,
+            },
+            0,
+        );
+
+        assert_eq!(frame[Direction::N.index()], 255);
+        assert!(frame.iter().all(|&brightness| brightness > 0));
+    }
+
+    #[test]
+    fn bounce() {
+        let bounce = |elapsed| frame_at(Pattern::Bounce { step: 100 }, elapsed);
+
+        assert_eq!(bounce(0), [255, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(bounce(400), [0, 0, 0, 0, 255, 0, 0, 0]);
+        assert_eq!(bounce(500), [0, 0, 0, 255, 0, 0, 0, 0]);
+        assert_eq!(bounce(700), [0, 255, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(bounce(800), [255, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn spin() {
+        let spin = |elapsed| frame_at(Pattern::Spin { step: 60 }, elapsed);
+
+        assert_eq!(spin(0), [255, 0, 0, 0, 255, 0, 0, 0]);
+        assert_eq!(spin(60), [0, 255, 0, 0, 0, 255, 0, 0]);
+        assert_eq!(spin(4 * 60), [255, 0, 0, 0, 255, 0, 0, 0]);
+    }
+
+    #[test]
+    fn reverse_plays_backwards() {
+        let mut animation = Animation::new(Pattern::Chase { step: 100, tail: 0 });
+        animation.reverse();
+
+        assert_eq!(animation.tick(0), keyframe(Direction::N, 0).frame);
+        assert_eq!(animation.tick(100), keyframe(Direction::NW, 0).frame);
+        assert_eq!(animation.tick(300), keyframe(Direction::SW, 0).frame);
+
+        // And forwards again from where it was
+        animation.reverse();
+        assert_eq!(animation.tick(500), keyframe(Direction::NW, 0).frame);
+    }
+
+    #[test]
+    fn reverse_ignored_by_non_turning_patterns() {
+        let mut animation = Animation::new(Pattern::Breathe { period: 1_000 });
+        animation.reverse();
+        animation.tick(0);
+
+        assert_eq!(animation.tick(250), [127; 8]);
+    }
+
+    #[test]
+    fn set_speed_scales_position() {
+        let mut animation = Animation::new(Pattern::Chase { step: 100, tail: 0 });
+        animation.set_speed(200);
+        animation.tick(0);
+        assert_eq!(animation.tick(100), keyframe(Direction::E, 0).frame);
+
+        // 50 ms at half speed is 25 ms into the pattern, odd speeds don't lose time
+        animation.set_speed(50);
+        for now in (105..=300).step_by(5) {
+            animation.tick(now);
+        }
+        assert_eq!(animation.frame(), keyframe(Direction::SE, 0).frame);
+
+        animation.set_speed(0);
+        assert_eq!(animation.tick(10_000), keyframe(Direction::SE, 0).frame);
+    }
+
+    #[test]
+    fn roulette() {
+        let roulette = |elapsed| frame_at(Pattern::Keyframes(Keyframes::roulette()), elapsed);
+
+        assert_eq!(Keyframes::roulette().duration(), 8 * 150);
+        assert_eq!(roulette(0), [255, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(roulette(100), [255, 255, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(roulette(150), [0, 255, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(roulette(1_150), [255, 0, 0, 0, 0, 0, 0, 255]);
+        assert_eq!(roulette(1_200), [255, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn repeat_holds_the_last_frame() {
+        let keyframes = Keyframes::new(&[keyframe(Direction::N, 100), keyframe(Direction::S, 100)])
+            .unwrap()
+            .repeat(Some(2));
+        let mut animation = Animation::new(Pattern::Keyframes(keyframes));
+        animation.tick(0);
+
+        assert_eq!(animation.tick(250), keyframe(Direction::N, 0).frame);
+        assert_eq!(animation.tick(399), keyframe(Direction::S, 0).frame);
+        assert!(!animation.is_finished());
+
+        assert_eq!(animation.tick(400), keyframe(Direction::S, 0).frame);
+        assert_eq!(animation.tick(1_000), keyframe(Direction::S, 0).frame);
+        assert!(animation.is_finished());
+    }
+
+    #[test]
+    fn repeat_forever() {
+        let keyframes =
+            Keyframes::new(&[keyframe(Direction::N, 100), keyframe(Direction::S, 100)]).unwrap();
+        let mut animation = Animation::new(Pattern::Keyframes(keyframes));
+        animation.tick(0);
+
+        assert_eq!(animation.tick(1_000_050), keyframe(Direction::N, 0).frame);
+        assert!(!animation.is_finished());
+    }
+
+    #[test]
+    fn repeat_too_long_to_count() {
+        let keyframes = Keyframes::new(&[
+            keyframe(Direction::N, 4_000_000_000),
+            keyframe(Direction::E, 4_000_000_000),
+        ])
+        .unwrap()
+        .repeat(Some(4_000_000_000));
+        let mut animation = Animation::new(Pattern::Keyframes(keyframes));
+        animation.tick(0);
+
+        assert_eq!(
+            animation.tick(5_000_000_000),
+            keyframe(Direction::E, 0).frame
+        );
+        assert!(!animation.is_finished());
+    }
+}
@@ -0,0 +1,225 @@
+//! The eight points of the compass rose
+
+use core::fmt;
+
+/// A compass LED, the discovery board's "north" is the edge with the USB connectors
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// LD3, red
+    N,
+    /// LD5, orange
+    NE,
+    /// LD7, green
+    E,
+    /// LD9, blue
+    SE,
+    /// LD10, red
+    S,
+    /// LD8, orange
+    SW,
+    /// LD6, green
+    W,
+    /// LD4, blue
+    NW,
+}
+
+impl Direction {
+    /// Clockwise, starting with north
+    pub const ALL: [Direction; 8] = [
+        Direction::N,
+        Direction::NE,
+        Direction::E,
+        Direction::SE,
+        Direction::S,
+        Direction::SW,
+        Direction::W,
+        Direction::NW,
+    ];
+
+    /// Position in `ALL`, and in the LED arrays
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// The direction at `index` in `ALL`, wrapping around
+    pub fn from_index(index: usize) -> Self {
+        Direction::ALL[index % 8]
+    }
+
+    /// The direction closest to a heading in degrees: 0 is north, 90 is east. Any angle works,
+    /// e.g. -90 and 270 are both west. Halfway between two directions (22.5, 67.5, ...) goes to
+    /// the clockwise one
+    pub fn from_degrees(degrees: f32) -> Self {
+        let degrees = degrees % 360.0;
+        let degrees = if degrees < 0.0 {
+            degrees + 360.0
+        } else {
+            degrees
+        };
+
+        // NaN (and infinity, whose remainder is NaN) ends up as north
+        Direction::from_index(((degrees + 22.5) / 45.0) as usize)
+    }
+
+    /// Heading in degrees: 0 for north, 45 for north-east, ...
+    pub fn degrees(self) -> f32 {
+        self.index() as f32 * 45.0
+    }
+
+    /// Rotates by `steps` eighths of a turn, clockwise if positive
+    pub fn rotate(self, steps: i32) -> Self {
+        // Reduced first, adding any `steps` to the index could overflow
+        Direction::from_index(self.index() + steps.rem_euclid(8) as usize)
+    }
+
+    /// The next direction clockwise
+    pub fn clockwise(self) -> Self {
+        self.rotate(1)
+    }
+
+    /// The next direction counterclockwise
+    pub fn counterclockwise(self) -> Self {
+        self.rotate(-1)
+    }
+
+    pub fn opposite(self) -> Self {
+        self.rotate(4)
+    }
+
+    /// All directions clockwise, starting with `self`
+    pub fn clockwise_from(self) -> impl Iterator<Item = Direction> {
+        (0..8).map(move |step| self.rotate(step))
+    }
+
+    /// All directions counterclockwise, starting with `self`
+    pub fn counterclockwise_from(self) -> impl Iterator<Item = Direction> {
+        (0..8).map(move |step| self.rotate(-step))
+    }
+
+    /// GPIOE pin the LED is connected to
+    pub fn pin(self) -> u8 {
+        // PE8 is NW, then clockwise from N: PE9, PE10, ...
+        8 + (self.index() as u8 + 1) % 8
+    }
+
+    /// The direction abbreviated `name` (e.g. "NE"), in any case
+    pub fn from_name(name: &str) -> Option<Self> {
+        Direction::ALL
+            .into_iter()
+            .find(|direction| direction.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Abbreviation, e.g. "NE"
+    pub fn name(self) -> &'static str {
+        match self {
+            Direction::N => "N",
+            Direction::NE => "NE",
+            Direction::E => "E",
+            Direction::SE => "SE",
+            Direction::S => "S",
+            Direction::SW => "SW",
+            Direction::W => "W",
+            Direction::NW => "NW",
+        }
+    }
+}
+
+impl From<Direction> for usize {
+    fn from(direction: Direction) -> usize {
+        direction.index()
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_degrees_cardinal_points() {
+        for direction in Direction::ALL {
+            assert_eq!(Direction::from_degrees(direction.degrees()), direction);
+        }
+    }
+
+    #[test]
+    fn from_degrees_not_finite() {
+        assert_eq!(Direction::from_degrees(f32::NAN), Direction::N);
+        assert_eq!(Direction::from_degrees(f32::INFINITY), Direction::N);
+        assert_eq!(Direction::from_degrees(f32::NEG_INFINITY), Direction::N);
+    }
+
+    #[test]
+    fn from_degrees_negative() {
+        assert_eq!(Direction::from_degrees(-0.0), Direction::N);
+        assert_eq!(Direction::from_degrees(-45.0), Direction::NW);
+        assert_eq!(Direction::from_degrees(-90.0), Direction::W);
+        assert_eq!(Direction::from_degrees(-180.0), Direction::S);
+        assert_eq!(Direction::from_degrees(-360.0), Direction::N);
+        assert_eq!(Direction::from_degrees(-720.0 - 135.0), Direction::SW);
+        // Rounds to 360 once wrapped
+        assert_eq!(Direction::from_degrees(-0.000_01), Direction::N);
+    }
+
+    #[test]
+    fn from_degrees_wraps() {
+        assert_eq!(Direction::from_degrees(360.0), Direction::N);
+        assert_eq!(Direction::from_degrees(405.0), Direction::NE);
+        assert_eq!(Direction::from_degrees(3_600.0 + 270.0), Direction::W);
+    }
+
+    #[test]
+    fn from_degrees_boundaries() {
+        for direction in Direction::ALL {
+            let boundary = direction.degrees() + 22.5;
+
+            assert_eq!(Direction::from_degrees(boundary - 0.01), direction);
+            assert_eq!(Direction::from_degrees(boundary), direction.clockwise());
+            assert_eq!(
+                Direction::from_degrees(boundary + 0.01),
+                direction.clockwise()
+            );
+        }
+
+        assert_eq!(Direction::from_degrees(-22.5), Direction::N);
+        assert_eq!(Direction::from_degrees(-22.51), Direction::NW);
+    }
+
+    #[test]
+    fn rotate() {
+        assert_eq!(Direction::N.rotate(0), Direction::N);
+        assert_eq!(Direction::N.rotate(1), Direction::NE);
+        assert_eq!(Direction::N.rotate(-1), Direction::NW);
+        assert_eq!(Direction::SE.rotate(3), Direction::W);
+        assert_eq!(Direction::SE.rotate(-3), Direction::N);
+        assert_eq!(Direction::E.rotate(8), Direction::E);
+        assert_eq!(Direction::E.rotate(-16), Direction::E);
+        assert_eq!(Direction::NW.rotate(i32::MAX), Direction::W);
+        assert_eq!(Direction::NW.rotate(i32::MIN), Direction::NW);
+    }
+
+    #[test]
+    fn rotate_inverse() {
+        for direction in Direction::ALL {
+            for steps in -9..=9 {
+                assert_eq!(direction.rotate(steps).rotate(-steps), direction);
+            }
+
+            assert_eq!(direction.clockwise().counterclockwise(), direction);
+            assert_eq!(direction.opposite().opposite(), direction);
+        }
+    }
+
+    #[test]
+    fn pin() {
+        let pins = Direction::ALL.map(Direction::pin);
+
+        assert_eq!(pins, [9, 10, 11, 12, 13, 14, 15, 8]);
+        assert_eq!(Direction::NW.pin(), 8);
+    }
+}
@@ -0,0 +1,52 @@
+//! The eight user LEDs in compass terms
+//!
+//! The LEDs of the STM32F3DISCOVERY form a compass rose. Instead of array indices and pin
+//! numbers they can be addressed by `Direction`:
+//!
+//! ``` ignore
+//! use compass_leds::{CompassLeds, Direction};
+//!
+//! leds.get_mut(Direction::from_degrees(heading)).on().ok();
+//!
+//! for (direction, led) in leds.iter_clockwise() {
+//!     ...
+//! }
+//! ```
+//!
+//! LED arrays (e.g. `LedArray`) are ordered clockwise starting with north, that is the order of
+//! `Direction::ALL`.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod animation;
+pub mod direction;
+pub mod script;
+
+pub use animation::{Animation, Frame, Keyframe, Keyframes, Pattern};
+pub use direction::Direction;
+
+use core::{array, iter::Zip, slice};
+
+/// Access to the eight compass LEDs by `Direction`
+pub trait CompassLeds<T> {
+    fn get(&self, direction: Direction) -> &T;
+
+    fn get_mut(&mut self, direction: Direction) -> &mut T;
+
+    /// Every LED with its direction, clockwise starting with north
+    fn iter_clockwise(&self) -> Zip<array::IntoIter<Direction, 8>, slice::Iter<'_, T>>;
+}
+
+impl<T> CompassLeds<T> for [T; 8] {
+    fn get(&self, direction: Direction) -> &T {
+        &self[direction.index()]
+    }
+
+    fn get_mut(&mut self, direction: Direction) -> &mut T {
+        &mut self[direction.index()]
+    }
+
+    fn iter_clockwise(&self) -> Zip<array::IntoIter<Direction, 8>, slice::Iter<'_, T>> {
+        Direction::ALL.into_iter().zip(self.iter())
+    }
+}
@@ -0,0 +1,351 @@
+//! A small language for keyframe animations
+//!
+//! A script is a list of statements separated by `;` or line breaks, `#` starts a comment:
+//!
+//! ``` text
+//! N+E 100; S 50; all- 200; repeat 3
+//! ```
+//!
+//! - `<leds> <ms>` turns LEDs on and shows the result for `<ms>` milliseconds. `<leds>` is `all`
+//!   or directions joined with `+` (`N`, `NE`, .. in any case). A trailing `-` turns them off
+//!   instead, `@<level>` sets a brightness between 0 and 255. LEDs that are not listed keep
+//!   their state, everything starts off.
+//! - `repeat <n>` plays the whole script `n` times and then holds the last frame. Without it the
+//!   script loops forever.
+//!
+//! `parse` turns a script into `Keyframes`, one per `<leds>` statement. Errors point at the line
+//! and column (both starting at 1) where the problem is.
+
+use core::fmt;
+
+use crate::{
+    animation::{Frame, Keyframe, Keyframes, MAX_KEYFRAMES, OFF},
+    Direction,
+};
+
+/// What is wrong with a script
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Not `all` or a direction
+    UnknownLed,
+    /// A `<leds>` statement without a duration
+    MissingDuration,
+    /// Not a number, or out of range
+    InvalidNumber,
+    /// A duration of 0 ms
+    ZeroDuration,
+    /// `repeat` without a count, or a count of 0
+    InvalidRepeat,
+    /// More words than the statement takes
+    UnexpectedWord,
+    /// More than `MAX_KEYFRAMES` statements
+    TooManyKeyframes,
+    /// No `<leds>` statement at all
+    Empty,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::UnknownLed => f.write_str("expected `all` or a direction (N, NE, E, ..)"),
+            ErrorKind::MissingDuration => f.write_str("expected a duration in ms"),
+            ErrorKind::InvalidNumber => f.write_str("invalid number"),
+            ErrorKind::ZeroDuration => f.write_str("duration must be at least 1 ms"),
+            ErrorKind::InvalidRepeat => f.write_str("expected a repeat count of at least 1"),
+            ErrorKind::UnexpectedWord => f.write_str("unexpected word"),
+            ErrorKind::TooManyKeyframes => write!(f, "too many steps ({} max)", MAX_KEYFRAMES),
+            ErrorKind::Empty => f.write_str("no steps"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: ErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.kind
+        )
+    }
+}
+
+// A word of the script and where it starts
+#[derive(Clone, Copy)]
+struct Word<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Word<'a> {
+    fn error(&self, kind: ErrorKind) -> ParseError {
+        self.error_at(0, kind)
+    }
+
+    // An error `offset` bytes into the word
+    fn error_at(&self, offset: usize, kind: ErrorKind) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column + self.text[..offset].chars().count(),
+            kind,
+        }
+    }
+}
+
+/// Parses a script, see the module documentation
+pub fn parse(script: &str) -> Result<Keyframes, ParseError> {
+    let mut keyframes = Keyframes::default();
+    let mut repeat = None;
+    let mut frame = OFF;
+    // Where the script ends, for `Empty`
+    let mut end = (1, 1);
+
+    for (index, line) in script.split('\n').enumerate() {
+        // Drop the comment and a CR left over from a CRLF
+        let line = line.split('#').next().unwrap_or("").trim_end_matches('\r');
+        end = (index + 1, line.chars().count() + 1);
+
+        let mut start = 0;
+        for statement in line.split(';') {
+            let mut words = words(statement, index + 1, line[..start].chars().count() + 1);
+            start += statement.len() + 1;
+
+            let first = match words.next() {
+                Some(word) => word,
+                // Empty statement
+                None => continue,
+            };
+
+            if first.text.eq_ignore_ascii_case("repeat") {
+                let count = words
+                    .next()
+                    .ok_or_else(|| first.error(ErrorKind::InvalidRepeat))?;
+                match count.text.parse::<u32>() {
+                    Ok(0) => return Err(count.error(ErrorKind::InvalidRepeat)),
+                    Ok(times) => repeat = Some(times),
+                    Err(_) => return Err(count.error(ErrorKind::InvalidNumber)),
+                }
+            } else {
+                apply(&first, &mut frame)?;
+
+                let duration = words.next().ok_or_else(|| ParseError {
+                    line: first.line,
+                    column: first.column + first.text.chars().count(),
+                    kind: ErrorKind::MissingDuration,
+                })?;
+                let duration = match duration.text.parse::<u32>() {
+                    Ok(0) => return Err(duration.error(ErrorKind::ZeroDuration)),
+                    Ok(ms) => ms,
+                    Err(_) => return Err(duration.error(ErrorKind::InvalidNumber)),
+                };
+
+                keyframes
+                    .push(Keyframe { frame, duration })
+                    .map_err(|_| first.error(ErrorKind::TooManyKeyframes))?;
+            }
+
+            if let Some(word) = words.next() {
+                return Err(word.error(ErrorKind::UnexpectedWord));
+            }
+        }
+    }
+
+    if keyframes.frames().is_empty() {
+        return Err(ParseError {
+            line: end.0,
+            column: end.1,
+            kind: ErrorKind::Empty,
+        });
+    }
+
+    Ok(keyframes.repeat(repeat))
+}
+
+// The words of `statement`, which starts at `column` of `line`
+fn words(statement: &str, line: usize, column: usize) -> impl Iterator<Item = Word<'_>> {
+    let mut chars = statement.char_indices().peekable();
+    let mut count = 0;
+
+    core::iter::from_fn(move || {
+        // `start` is in bytes, to slice `statement`, `count` in chars, for the column
+        let (start, start_count) = loop {
+            let (index, c) = chars.next()?;
+            count += 1;
+            if !c.is_whitespace() {
+                break (index, count - 1);
+            }
+        };
+        let mut end = statement.len();
+        while let Some(&(index, c)) = chars.peek() {
+            if c.is_whitespace() {
+                end = index;
+                break;
+            }
+            chars.next();
+            count += 1;
+        }
+
+        Some(Word {
+            text: &statement[start..end],
+            line,
+            column: column + start_count,
+        })
+    })
+}
+
+// Applies a `<leds>` word, e.g. `N+E-` or `all@64`, to `frame`
+fn apply(word: &Word, frame: &mut Frame) -> Result<(), ParseError> {
+    let (leds, level) = match word.text.split_once('@') {
+        Some((leds, level)) => {
+            let level = level
+                .parse::<u8>()
+                .map_err(|_| word.error_at(leds.len() + 1, ErrorKind::InvalidNumber))?;
+            (leds, level)
+        }
+        None => match word.text.strip_suffix('-') {
+            Some(leds) => (leds, 0),
+            None => (word.text, 255),
+        },
+    };
+
+    let mut offset = 0;
+    for name in leds.split('+') {
+        if name.eq_ignore_ascii_case("all") {
+            *frame = [level; 8];
+        } else {
+            let direction = Direction::from_name(name)
+                .ok_or_else(|| word.error_at(offset, ErrorKind::UnknownLed))?;
+            frame[direction.index()] = level;
+        }
+        offset += name.len() + 1;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(leds: &[Direction], level: u8) -> Frame {
+        let mut frame = OFF;
+        for led in leds {
+            frame[led.index()] = level;
+        }
+        frame
+    }
+
+    fn error(script: &str) -> (usize, usize, ErrorKind) {
+        let error = parse(script).unwrap_err();
+        (error.line, error.column, error.kind)
+    }
+
+    #[test]
+    fn sample() {
+        let keyframes = parse("N+E 100; S 50; all- 200; repeat 3").unwrap();
+
+        let expected = Keyframes::new(&[
+            Keyframe {
+                frame: frame(&[Direction::N, Direction::E], 255),
+                duration: 100,
+            },
+            Keyframe {
+                frame: frame(&[Direction::N, Direction::E, Direction::S], 255),
+                duration: 50,
+            },
+            Keyframe {
+                frame: OFF,
+                duration: 200,
+            },
+        ])
+        .unwrap()
+        .repeat(Some(3));
+        assert_eq!(keyframes, expected);
+    }
+
+    #[test]
+    fn levels_and_case() {
+        let keyframes = parse("all@64 10\nne+sW@0 20").unwrap();
+
+        let mut last = [64; 8];
+        last[Direction::NE.index()] = 0;
+        last[Direction::SW.index()] = 0;
+        assert_eq!(keyframes.frames()[0].frame, [64; 8]);
+        assert_eq!(keyframes.frames()[1].frame, last);
+    }
+
+    #[test]
+    fn comments_and_line_breaks() {
+        let script = "# warm up\nN 100 # north only\n\n  # nothing here\nS 50\n";
+        let keyframes = parse(script).unwrap();
+
+        assert_eq!(keyframes, parse("N 100; S 50").unwrap());
+    }
+
+    #[test]
+    fn crlf() {
+        assert_eq!(
+            parse("N 100\r\nS 50\r\n").unwrap(),
+            parse("N 100; S 50").unwrap()
+        );
+        assert_eq!(error("N 100\r\nS\r\n"), (2, 2, ErrorKind::MissingDuration));
+    }
+
+    #[test]
+    fn non_ascii() {
+        // No-break space and ideographic space are whitespace too
+        assert_eq!(
+            parse("N\u{a0}100;\u{3000}S 50").unwrap(),
+            parse("N 100; S 50").unwrap()
+        );
+        // Columns count chars, not bytes
+        assert_eq!(error("\u{a0}N\u{a0}1x"), (1, 4, ErrorKind::InvalidNumber));
+        assert_eq!(error("é 100"), (1, 1, ErrorKind::UnknownLed));
+        assert_eq!(error("N 100; ü 1"), (1, 8, ErrorKind::UnknownLed));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("N 100\nN+X 10"), (2, 3, ErrorKind::UnknownLed));
+        assert_eq!(error("N 100; E"), (1, 9, ErrorKind::MissingDuration));
+        assert_eq!(error("N 1e3"), (1, 3, ErrorKind::InvalidNumber));
+        assert_eq!(error("N@256 10"), (1, 3, ErrorKind::InvalidNumber));
+        assert_eq!(error("N 4294967296"), (1, 3, ErrorKind::InvalidNumber));
+        assert_eq!(error("N 100\n  S 0"), (2, 5, ErrorKind::ZeroDuration));
+        assert_eq!(error("N 100; repeat"), (1, 8, ErrorKind::InvalidRepeat));
+        assert_eq!(error("N 100; repeat 0"), (1, 15, ErrorKind::InvalidRepeat));
+        assert_eq!(error("N 100; repeat x"), (1, 15, ErrorKind::InvalidNumber));
+        assert_eq!(error("N 100 200"), (1, 7, ErrorKind::UnexpectedWord));
+        assert_eq!(error("repeat 2 3"), (1, 10, ErrorKind::UnexpectedWord));
+        assert_eq!(error("# nothing\n;;"), (2, 3, ErrorKind::Empty));
+        assert_eq!(error(""), (1, 1, ErrorKind::Empty));
+    }
+
+    #[test]
+    fn too_many_keyframes() {
+        let mut script = String::new();
+        for _ in 0..MAX_KEYFRAMES {
+            script.push_str("N 1\n");
+        }
+        script.push_str("S 1");
+
+        assert_eq!(
+            error(&script),
+            (MAX_KEYFRAMES + 1, 1, ErrorKind::TooManyKeyframes)
+        );
+    }
+
+    #[test]
+    fn long_repeat() {
+        let keyframes = parse("N 4000000000; E 4000000000; repeat 4000000000").unwrap();
+
+        assert_eq!(keyframes.duration(), 8_000_000_000);
+    }
+}
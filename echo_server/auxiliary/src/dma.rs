@@ -0,0 +1,204 @@
+//! DMA backed transmit queue for USART1
+//!
+//! USART1_TX is wired to channel 4 of DMA1. Bytes are copied into one of two buffers while the
+//! other one is being sent, when a transfer completes the DMA1_CH4 interrupt starts the next
+//! one. The application has to forward that interrupt to `on_transfer_complete`:
+//!
+//! ``` ignore
+//! #[interrupt]
+//! fn DMA1_CH4() {
+//!     aux11::dma::on_transfer_complete();
+//! }
+//! ```
+
+use cortex_m::{interrupt, peripheral::NVIC};
+use executor::Signal;
+use stm32f3_discovery::stm32f3xx_hal::pac::{self, Interrupt, DMA1, USART1};
+
+/// Size of each of the two transmit buffers
+pub const TX_BUFFER_SIZE: usize = 64;
+
+struct State {
+    buffers: [[u8; TX_BUFFER_SIZE]; 2],
+    // Number of bytes queued in each buffer
+    len: [usize; 2],
+    // Index of the buffer that is being filled, the other one may be owned by the DMA
+    fill: usize,
+    // Is the DMA currently sending the other buffer?
+    busy: bool,
+}
+
+// Woken whenever a transfer completes, which frees a buffer
+static TX_DONE: Signal = Signal::new();
+
+// NOTE only ever accessed inside a critical section
+static mut STATE: State = State {
+    buffers: [[0; TX_BUFFER_SIZE]; 2],
+    len: [0; 2],
+    fill: 0,
+    busy: false,
+};
+
+/// Handle to the USART1 DMA transmit queue
+pub struct TxQueue {
+    _private: (),
+}
+
+impl TxQueue {
+    /// Enables DMA1 and the USART1 DMA transmit request
+    ///
+    /// There must only be one `TxQueue`
+    pub fn new() -> Self {
+        // NOTE(unsafe) we only set bits that nothing else in aux11 uses
+        unsafe {
+            let rcc = &*pac::RCC::ptr();
+            let usart1 = &*USART1::ptr();
+
+            rcc.ahbenr.modify(|_, w| w.dma1en().set_bit());
+            // DMAT: Ask the DMA for a new byte every time TDR is empty
+            usart1.cr3.modify(|_, w| w.dmat().set_bit());
+
+            NVIC::unmask(Interrupt::DMA1_CH4);
+        }
+
+        TxQueue { _private: () }
+    }
+
+    /// Queues `bytes` for transmission
+    ///
+    /// This only blocks if both buffers are full, in which case it waits for the current
+    /// transfer to complete
+    pub fn write(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let queued = self.try_write(bytes);
+            bytes = &bytes[queued..];
+        }
+    }
+
+    /// Queues as much of `bytes` as fits without waiting, returns how many bytes were queued
+    pub fn try_write(&mut self, bytes: &[u8]) -> usize {
+        interrupt::free(|_| {
+            // NOTE(unsafe) inside a critical section
+            let state = unsafe { &mut STATE };
+
+            let fill = state.fill;
+            let start = state.len[fill];
+            let n = core::cmp::min(TX_BUFFER_SIZE - start, bytes.len());
+            state.buffers[fill][start..start + n].copy_from_slice(&bytes[..n]);
+            state.len[fill] += n;
+
+            start_transfer(state);
+
+            n
+        })
+    }
+
+    /// `write` for `async` code, waits for room in the buffers without blocking other tasks
+    pub async fn write_async(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let queued = TX_DONE
+                .wait(|| match self.try_write(bytes) {
+                    0 => None,
+                    n => Some(n),
+                })
+                .await;
+            bytes = &bytes[queued..];
+        }
+    }
+
+    /// Returns `true` if `try_write` can queue at least one byte
+    pub fn has_room(&self) -> bool {
+        interrupt::free(|_| {
+            // NOTE(unsafe) inside a critical section
+            let state = unsafe { &STATE };
+
+            state.len[state.fill] < TX_BUFFER_SIZE
+        })
+    }
+
+    /// Returns `true` once every queued byte has been handed to the USART
+    pub fn is_idle(&self) -> bool {
+        interrupt::free(|_| {
+            // NOTE(unsafe) inside a critical section
+            let state = unsafe { &STATE };
+
+            !state.busy && state.len[state.fill] == 0
+        })
+    }
+
+    /// Blocks until every queued byte has been transmitted
+    pub fn flush(&mut self) {
+        while !self.is_idle() {}
+
+        wait_for_shift_register();
+    }
+
+    /// `flush` for `async` code
+    pub async fn flush_async(&mut self) {
+        TX_DONE.wait(|| self.is_idle().then_some(())).await;
+
+        wait_for_shift_register();
+    }
+}
+
+// The DMA is done, but the last byte may still be in the shift register. That takes one byte
+// time at most, not worth sleeping for
+fn wait_for_shift_register() {
+    // NOTE(unsafe) read only access
+    let usart1 = unsafe { &*USART1::ptr() };
+    while usart1.isr.read().tc().bit_is_clear() {}
+}
+
+/// Must be called from the DMA1_CH4 interrupt handler
+pub fn on_transfer_complete() {
+    interrupt::free(|_| {
+        // NOTE(unsafe) inside a critical section
+        let (dma1, state) = unsafe { (&*DMA1::ptr(), &mut STATE) };
+
+        if dma1.isr.read().tcif4().bit_is_clear() {
+            return;
+        }
+
+        // CTCIF4: Clear the transfer complete flag, EN: the channel has to be disabled before
+        // it can be given a new buffer
+        dma1.ifcr.write(|w| w.ctcif4().set_bit());
+        dma1.ch4.cr.modify(|_, w| w.en().clear_bit());
+        state.busy = false;
+
+        start_transfer(state);
+    });
+
+    TX_DONE.wake();
+}
+
+// Hands the buffer that is being filled over to the DMA, if the DMA is idle and there is
+// something to send
+fn start_transfer(state: &mut State) {
+    let fill = state.fill;
+    if state.busy || state.len[fill] == 0 {
+        return;
+    }
+
+    // NOTE(unsafe) `state.busy` guarantees nobody else is using channel 4
+    let (dma1, usart1) = unsafe { (&*DMA1::ptr(), &*USART1::ptr()) };
+
+    dma1.ch4
+        .par
+        .write(|w| unsafe { w.pa().bits(&usart1.tdr as *const _ as u32) });
+    dma1.ch4
+        .mar
+        .write(|w| unsafe { w.ma().bits(state.buffers[fill].as_ptr() as u32) });
+    dma1.ch4.ndtr.write(|w| w.ndt().bits(state.len[fill] as u16));
+    // DIR: Read from memory, MINC: Advance through the buffer, PINC is left cleared so every
+    // byte goes to TDR, TCIE: Interrupt once the last byte has been moved
+    dma1.ch4.cr.write(|w| {
+        w.dir().set_bit();
+        w.minc().set_bit();
+        w.tcie().set_bit();
+        w.en().set_bit()
+    });
+
+    state.busy = true;
+    state.fill = fill ^ 1;
+    state.len[state.fill] = 0;
+}
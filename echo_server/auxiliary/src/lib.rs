@@ -5,33 +5,69 @@
 #[allow(unused_extern_crates)] // NOTE(allow) bug rust-lang/rust53964
 extern crate panic_itm; // panic handler
 
-pub use cortex_m::{asm::bkpt, iprint, iprintln, peripheral::ITM};
-pub use cortex_m_rt::entry;
-pub use stm32f3_discovery::stm32f3xx_hal::pac::usart1;
+pub use cortex_m::{
+    asm::bkpt,
+    iprint, iprintln,
+    peripheral::{ITM, NVIC},
+};
+pub use aux_common::{idle, profile};
+pub use clocks::ClockProfile;
+pub use compass_leds::{animation, script, Animation, CompassLeds, Direction, Frame, Pattern};
+pub use cortex_m_rt::{entry, exception};
+pub use led_pwm::{self as pwm, LedArray, LedPwm};
+pub use stm32f3_discovery::stm32f3xx_hal::pac::{interrupt, usart1, Interrupt, USART1};
+pub use stm32f3_discovery::switch_hal::{OutputSwitch, ToggleableOutputSwitch};
 
+pub mod dma;
+pub mod framing;
+pub mod magnetometer;
 pub mod monotimer;
+pub mod serial;
+pub mod time;
 
-use stm32f3_discovery::stm32f3xx_hal::{
-    prelude::*,
-    serial::Serial,
-    pac::{self, USART1},
+use stm32f3_discovery::{
+    leds::Leds,
+    stm32f3xx_hal::{
+        i2c::I2c,
+        prelude::*,
+        rcc::CFGR,
+        serial::Serial,
+        pac,
+    },
 };
+use clocks::{timer_clock, HSE_FREQUENCY};
+use magnetometer::Magnetometer;
 use monotimer::MonoTimer;
+use serial::{FlowControl, SerialConfig};
 
-pub fn init() -> (&'static mut usart1::RegisterBlock, MonoTimer, ITM) {
+/// The LEDs are dimmable, an application that dims them has to forward the TIM1_UP_TIM16
+/// interrupt to `pwm::on_update`
+pub fn init(
+    profile: ClockProfile,
+    config: SerialConfig,
+) -> (
+    &'static mut usart1::RegisterBlock,
+    MonoTimer,
+    ITM,
+    LedPwm,
+    Magnetometer,
+) {
     let cp = cortex_m::Peripherals::take().unwrap();
     let dp = pac::Peripherals::take().unwrap();
 
     let mut flash = dp.FLASH.constrain();
     let mut rcc = dp.RCC.constrain();
 
-    let clocks = rcc.cfgr.freeze(&mut flash.acr);
+    let clocks = configure_clocks(rcc.cfgr, &profile).freeze(&mut flash.acr);
+    profile
+        .check(clocks.sysclk().0, clocks.hclk().0, clocks.pclk1().0, clocks.pclk2().0)
+        .unwrap_or_else(|mismatch| panic!("{}", mismatch));
+
+    let mut gpioa = dp.GPIOA.split(&mut rcc.ahb);
 
     let (tx, rx) = match () {
         #[cfg(feature = "adapter")]
         () => {
-            let mut gpioa = dp.GPIOA.split(&mut rcc.ahb);
-
             let tx = gpioa.pa9.into_af7_push_pull(&mut gpioa.moder, &mut gpioa.otyper, &mut gpioa.afrh);
             let rx = gpioa.pa10.into_af7_push_pull(&mut gpioa.moder, &mut gpioa.otyper, &mut gpioa.afrh);
 
@@ -48,16 +84,69 @@ pub fn init() -> (&'static mut usart1::RegisterBlock, MonoTimer, ITM) {
         }
     };
 
-    Serial::new(dp.USART1, (tx, rx), 9600.Bd(), clocks, &mut rcc.apb2);
-    // If you are having trouble sending/receiving data to/from the
-    // HC-05 bluetooth module, try this configuration instead:
-    // Serial::usart1(dp.USART1, (tx, rx), 9600.bps(), clocks, &mut rcc.apb2);
-
-    unsafe {
-        (
-            &mut *(USART1::ptr() as *mut _),
-            MonoTimer::new(cp.DWT, clocks),
-            cp.ITM,
-        )
+    // USART1 only has CTS / RTS on PA11 / PA12, whichever pins carry TX / RX
+    if config.flow_control == FlowControl::RtsCts {
+        gpioa.pa11.into_af7_push_pull(&mut gpioa.moder, &mut gpioa.otyper, &mut gpioa.afrh);
+        gpioa.pa12.into_af7_push_pull(&mut gpioa.moder, &mut gpioa.otyper, &mut gpioa.afrh);
     }
-}
\ No newline at end of file
+
+    // Let the HAL power on USART1 and route the pins, then apply `config` ourselves. The HAL
+    // asserts on a baud rate it can't generate, so it gets one that works with every
+    // `ClockProfile`, `serial::configure` checks `config.baud_rate` and reports it instead.
+    // If you are having trouble sending/receiving data to/from the HC-05 bluetooth module, its
+    // factory setting is 9600 8N1, or enable `config.auto_baud` and let the board figure it out
+    Serial::new(dp.USART1, (tx, rx), 9_600.Bd(), clocks, &mut rcc.apb2);
+
+    let usart1 = unsafe { &mut *(USART1::ptr() as *mut usart1::RegisterBlock) };
+    usart1.cr1.modify(|_, w| w.ue().clear_bit());
+    serial::configure(usart1, clocks.pclk2().0, &config)
+        .unwrap_or_else(|error| panic!("{}", error));
+    usart1.cr1.modify(|_, w| w.ue().set_bit());
+
+    // TIM6 is on APB1
+    time::set_clock(timer_clock(clocks.pclk1().0, clocks.ppre1()));
+
+    // Let the HAL power on I2C1 and work out its timing, `Magnetometer` then uses the registers
+    let mut gpiob = dp.GPIOB.split(&mut rcc.ahb);
+    let scl = gpiob.pb6.into_af4_open_drain(&mut gpiob.moder, &mut gpiob.otyper, &mut gpiob.afrl);
+    let sda = gpiob.pb7.into_af4_open_drain(&mut gpiob.moder, &mut gpiob.otyper, &mut gpiob.afrl);
+    I2c::new(dp.I2C1, (scl, sda), 400_000.Hz(), clocks, &mut rcc.apb1);
+
+    let mut gpioe = dp.GPIOE.split(&mut rcc.ahb);
+    let leds = Leds::new(
+        gpioe.pe8,
+        gpioe.pe9,
+        gpioe.pe10,
+        gpioe.pe11,
+        gpioe.pe12,
+        gpioe.pe13,
+        gpioe.pe14,
+        gpioe.pe15,
+        &mut gpioe.moder,
+        &mut gpioe.otyper,
+    );
+
+    (
+        usart1,
+        MonoTimer::new(cp.DWT, cp.SYST, clocks),
+        cp.ITM,
+        LedPwm::new(leds.into_array(), dp.TIM1, dp.TIM16, clocks),
+        // NOTE(unsafe) I2C1 is only used through `Magnetometer`
+        Magnetometer::new(unsafe { &*pac::I2C1::ptr() }),
+    )
+}
+
+// Hands `profile` to the HAL, which picks the PLL multiplier, the prescalers and the flash wait
+// states
+fn configure_clocks(cfgr: CFGR, profile: &ClockProfile) -> CFGR {
+    let cfgr = if profile.uses_hse() {
+        cfgr.use_hse(HSE_FREQUENCY.Hz()).bypass_hse()
+    } else {
+        cfgr
+    };
+
+    cfgr.sysclk(profile.sysclk.Hz())
+        .hclk(profile.hclk.Hz())
+        .pclk1(profile.pclk1.Hz())
+        .pclk2(profile.pclk2.Hz())
+}
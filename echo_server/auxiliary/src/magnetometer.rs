@@ -0,0 +1,115 @@
+//! The LSM303AGR magnetometer, over I2C1
+//!
+//! The same register accesses as in `i2c`'s `main.rs`, busy waiting on the ISR flags. A reading
+//! takes a few hundred µs at 400 kHz, short enough to do from a scheduler task:
+//!
+//! ``` ignore
+//! magnetometer.enable()?;
+//!
+//! let (x, y, z) = magnetometer.read()?;
+//! ```
+
+use core::fmt;
+
+use stm32f3_discovery::stm32f3xx_hal::pac::i2c1::{isr, RegisterBlock};
+
+// Slave address
+const MAGNETOMETER: u16 = 0b0011_1100;
+
+// Addresses of the magnetometer's registers
+const CFG_REG_A_M: u8 = 0x60;
+const OUTX_L_REG_M: u8 = 0x68;
+
+// CFG_REG_A_M: continuous mode, 50 Hz output data rate
+const CONTINUOUS_50HZ: u8 = 0b0000_1000;
+
+/// The magnetometer didn't answer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Nack;
+
+impl fmt::Display for Nack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("no answer from the magnetometer (NACK)")
+    }
+}
+
+pub struct Magnetometer {
+    i2c1: &'static RegisterBlock,
+}
+
+impl Magnetometer {
+    pub(crate) fn new(i2c1: &'static RegisterBlock) -> Self {
+        Magnetometer { i2c1 }
+    }
+
+    /// Starts continuous measurements, `read` returns the latest one
+    pub fn enable(&mut self) -> Result<(), Nack> {
+        // Broadcast START and the address with the R/W bit set to Write, STOP is automatic
+        self.i2c1.cr2.write(|w| {
+            w.start().set_bit();
+            w.sadd().bits(MAGNETOMETER);
+            w.rd_wrn().clear_bit();
+            w.nbytes().bits(2);
+            w.autoend().set_bit()
+        });
+
+        for byte in [CFG_REG_A_M, CONTINUOUS_50HZ] {
+            self.wait_for(|isr| isr.txis().bit_is_set())?;
+            self.i2c1.txdr.write(|w| w.txdata().bits(byte));
+        }
+
+        Ok(())
+    }
+
+    /// The latest measurement, in raw units (1.5 milligauss each)
+    pub fn read(&mut self) -> Result<(i16, i16, i16), Nack> {
+        // Broadcast START and the address with the R/W bit set to Write
+        self.i2c1.cr2.write(|w| {
+            w.start().set_bit();
+            w.sadd().bits(MAGNETOMETER);
+            w.rd_wrn().clear_bit();
+            w.nbytes().bits(1);
+            w.autoend().clear_bit()
+        });
+
+        // Send the address of the first register we want to read
+        self.wait_for(|isr| isr.txis().bit_is_set())?;
+        self.i2c1.txdr.write(|w| w.txdata().bits(OUTX_L_REG_M));
+        self.wait_for(|isr| isr.tc().bit_is_set())?;
+
+        // Broadcast RESTART and the address with the R/W bit set to Read, STOP is automatic
+        self.i2c1.cr2.modify(|_, w| {
+            w.start().set_bit();
+            w.nbytes().bits(6);
+            w.rd_wrn().set_bit();
+            w.autoend().set_bit()
+        });
+
+        let mut buffer = [0u8; 6];
+        for byte in &mut buffer {
+            self.wait_for(|isr| isr.rxne().bit_is_set())?;
+            *byte = self.i2c1.rxdr.read().rxdata().bits();
+        }
+
+        let axis = |i: usize| i16::from_le_bytes([buffer[i], buffer[i + 1]]);
+
+        Ok((axis(0), axis(2), axis(4)))
+    }
+
+    // Waits until `flag` is set. On a NACK the I2C1 sends STOP by itself (AUTOEND or not), so
+    // there is nothing left to clean up besides the flag
+    fn wait_for(&self, flag: fn(&isr::R) -> bool) -> Result<(), Nack> {
+        loop {
+            let isr = self.i2c1.isr.read();
+
+            if isr.nackf().bit_is_set() {
+                self.i2c1.icr.write(|w| w.nackcf().set_bit());
+                return Err(Nack);
+            }
+
+            if flag(&isr) {
+                return Ok(());
+            }
+        }
+    }
+}
@@ -1,26 +1,51 @@
 use stm32f3_discovery::stm32f3xx_hal as hal;
 
-use cortex_m::peripheral::DWT;
+use core::{ops::Sub, time::Duration};
+
+use aux_common::cycles;
+use cortex_m::peripheral::{syst::SystClkSource, DWT, SYST};
+use embedded_time::{clock, fraction::Fraction, Clock};
 use hal::{
     rcc::Clocks,
     time::rate::Hertz,
 };
 
+// SysTick fires every this many cycles, which is way less than it takes CYCCNT to wrap
+const SYSTICK_RELOAD: u32 = 0x00FF_FFFF;
+
 /// A monotonic nondecreasing timer. This is a resurrection of MonoTimer from
 /// the stm32f3xx-hal where it got removed after 0.6.1.
+///
+/// The 32 bit CYCCNT counter of the DWT wraps after about 9 minutes at 8 MHz, so it is extended to
+/// 64 bits in software (by `aux_common::cycles`). For that to work the SysTick exception has to be
+/// forwarded to `on_systick`:
+///
+/// ``` ignore
+/// #[exception]
+/// fn SysTick() {
+///     aux11::monotimer::on_systick();
+/// }
+/// ```
+///
+/// `MonoTimer` also implements `embedded_time::Clock`, with a resolution of one microsecond.
 #[derive(Clone, Copy)]
 pub struct MonoTimer {
     frequency: Hertz,
 }
 
-// TODO: What about a refactoring to implement Clock from embedded-time?
 impl MonoTimer {
     /// Creates a new `Monotonic` timer
-    pub fn new(mut dwt: DWT, clocks: Clocks) -> Self {
-        dwt.enable_cycle_counter();
-
+    pub fn new(dwt: DWT, mut syst: SYST, clocks: Clocks) -> Self {
         // now the CYCCNT counter can't be stopped or resetted
-        drop(dwt);
+        cycles::start(dwt);
+
+        // SysTick makes sure every wrap of CYCCNT is noticed, even if nobody calls `now` for a
+        // while
+        syst.set_clock_source(SystClkSource::Core);
+        syst.set_reload(SYSTICK_RELOAD);
+        syst.clear_current();
+        syst.enable_interrupt();
+        syst.enable_counter();
 
         MonoTimer {
             frequency: clocks.hclk(),
@@ -32,23 +57,91 @@ impl MonoTimer {
         self.frequency
     }
 
+    /// Converts `duration` into ticks of this timer
+    pub fn ticks(self, duration: Duration) -> u64 {
+        let frequency = u64::from(self.frequency.0);
+
+        duration.as_secs() * frequency
+            + u64::from(duration.subsec_nanos()) * frequency / 1_000_000_000
+    }
+
+    /// Converts a number of ticks into microseconds
+    pub fn micros(self, ticks: u64) -> u64 {
+        let frequency = u64::from(self.frequency.0);
+
+        // Split to avoid overflowing the multiplication
+        ticks / frequency * 1_000_000 + ticks % frequency * 1_000_000 / frequency
+    }
+
+    /// Converts a number of ticks into milliseconds
+    pub fn millis(self, ticks: u64) -> u64 {
+        self.micros(ticks) / 1_000
+    }
+
+    /// Converts a number of ticks into a `Duration`
+    pub fn duration(self, ticks: u64) -> Duration {
+        let frequency = u64::from(self.frequency.0);
+        let nanos = ticks % frequency * 1_000_000_000 / frequency;
+
+        Duration::new(ticks / frequency, nanos as u32)
+    }
+
     /// Returns an `Instant` corresponding to "now"
     pub fn now(self) -> Instant {
-        Instant {
-            now: DWT::get_cycle_count(),
-        }
+        Instant { now: cycles::now() }
     }
 }
 
+impl Clock for MonoTimer {
+    type T = u64;
+
+    // The cycle count is converted in `try_now`, this way the scaling factor doesn't depend on
+    // the core clock frequency
+    const SCALING_FACTOR: Fraction = Fraction::new(1, 1_000_000);
+
+    fn try_now(&self) -> Result<embedded_time::Instant<Self>, clock::Error> {
+        Ok(embedded_time::Instant::new(self.now().as_micros(*self)))
+    }
+}
+
+/// Must be called from the SysTick exception handler
+pub fn on_systick() {
+    cycles::now();
+}
+
 /// A measurement of a monotonically nondecreasing clock
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Instant {
-    now: u32,
+    now: u64,
 }
 
 impl Instant {
     /// Ticks elapsed since the `Instant` was created
-    pub fn elapsed(self) -> u32 {
-        DWT::get_cycle_count().wrapping_sub(self.now)
+    pub fn elapsed(self) -> u64 {
+        cycles::now().saturating_sub(self.now)
+    }
+
+    /// Microseconds since the cycle counter was started
+    pub fn as_micros(self, timer: MonoTimer) -> u64 {
+        timer.micros(self.now)
     }
-}
\ No newline at end of file
+
+    /// Milliseconds since the cycle counter was started
+    pub fn as_millis(self, timer: MonoTimer) -> u64 {
+        timer.millis(self.now)
+    }
+
+    /// Ticks from `earlier` to `self`, `None` if `earlier` is later than `self`
+    pub fn checked_duration_since(self, earlier: Instant) -> Option<u64> {
+        self.now.checked_sub(earlier.now)
+    }
+}
+
+impl Sub for Instant {
+    type Output = u64;
+
+    /// Ticks from `earlier` to `self`, zero if `earlier` is actually later
+    fn sub(self, earlier: Instant) -> u64 {
+        self.now.saturating_sub(earlier.now)
+    }
+}
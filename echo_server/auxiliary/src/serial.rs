@@ -0,0 +1,477 @@
+//! Interrupt driven USART1 serial port
+//!
+//! Received bytes are moved into a ring buffer by the USART1 interrupt, transmitted bytes go
+//! through the DMA queue in `dma`. The application has to forward both interrupts:
+//!
+//! ``` ignore
+//! #[interrupt]
+//! fn DMA1_CH4() {
+//!     aux11::dma::on_transfer_complete();
+//! }
+//!
+//! #[interrupt]
+//! fn USART1_EXTI25() {
+//!     aux11::serial::on_interrupt();
+//! }
+//! ```
+//!
+//! Besides `core::fmt::Write`, `SerialPort` implements the `embedded-hal` 0.2 serial traits and
+//! the `embedded-io` traits so off-the-shelf drivers can use it.
+
+use core::{
+    convert::Infallible,
+    fmt,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use cortex_m::peripheral::NVIC;
+use executor::Signal;
+use heapless::spsc::{Consumer, Producer, Queue};
+use stm32f3_discovery::stm32f3xx_hal::pac::{usart1, Interrupt, USART1};
+
+use crate::{dma::TxQueue, monotimer::MonoTimer};
+
+mod config;
+
+pub use config::*;
+
+// Size of the receive ring buffer, it can hold `RX_CAPACITY - 1` bytes
+const RX_CAPACITY: usize = 64;
+// With hardware flow control, stop taking bytes out of RDR (which raises RTS) once the ring
+// buffer holds this many bytes, and start again once it is down to `RX_LOW_WATER`
+const RX_HIGH_WATER: usize = RX_CAPACITY - 8;
+const RX_LOW_WATER: usize = RX_CAPACITY / 2;
+
+// Bytes and errors seen by the USART1 interrupt handler, in the order they happened, waiting to
+// be read
+static mut RX_QUEUE: Queue<Received, RX_CAPACITY> = Queue::new();
+// Producer half of `RX_QUEUE`, only ever touched by the USART1 interrupt handler once it has
+// been handed over by `SerialPort::new`
+static mut RX_PRODUCER: Option<Producer<'static, Received, RX_CAPACITY>> = None;
+// Number of bytes that were dropped because `RX_QUEUE` was full
+static RX_OVERFLOWS: AtomicU32 = AtomicU32::new(0);
+// Number of times each `SerialError` happened, indexed by `SerialError as usize`
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_ERRORS: AtomicU32 = AtomicU32::new(0);
+static RX_ERRORS: [AtomicU32; 4] = [NO_ERRORS; 4];
+// Woken by the interrupt handler whenever a byte or an error arrives
+static RX_SIGNAL: Signal = Signal::new();
+
+/// Receive errors reported by the USART in ISR
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SerialError {
+    /// ORE: A byte arrived before the previous one was read from RDR, and got lost
+    Overrun = 0,
+    /// FE: The stop bit was not where it should be, usually a baud rate mismatch
+    Framing = 1,
+    /// NF: Noise was detected while sampling a byte. Only counted in `ErrorCounts`, the byte is
+    /// the majority vote of the samples and is kept, so reads never return it
+    Noise = 2,
+    /// PE: The parity bit didn't match the data
+    Parity = 3,
+    /// Nothing arrived in time, only returned by the `_timeout` reads
+    Timeout = 4,
+}
+
+impl fmt::Display for SerialError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            SerialError::Overrun => "overrun",
+            SerialError::Framing => "framing error",
+            SerialError::Noise => "noise detected",
+            SerialError::Parity => "parity error",
+            SerialError::Timeout => "timed out",
+        })
+    }
+}
+
+// An entry of the receive ring buffer
+#[derive(Clone, Copy)]
+enum Received {
+    Byte(u8),
+    // Reported by `read_byte` once the bytes that arrived before it have been read
+    Error(SerialError),
+}
+
+/// How many times each `SerialError` happened since boot
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ErrorCounts {
+    pub overrun: u32,
+    pub framing: u32,
+    pub noise: u32,
+    pub parity: u32,
+}
+
+pub struct SerialPort {
+    usart1: &'static mut usart1::RegisterBlock,
+    rx: Consumer<'static, Received, RX_CAPACITY>,
+    tx: TxQueue,
+}
+
+impl SerialPort {
+    /// Splits the receive ring buffer and enables the RXNE interrupt, from now on every byte
+    /// that arrives is stored by `on_interrupt` until it is read
+    ///
+    /// There must only be one `SerialPort`
+    pub fn new(usart1: &'static mut usart1::RegisterBlock) -> Self {
+        // NOTE(unsafe) `SerialPort::new` is only called once, so there is a single consumer, and
+        // the interrupt that uses the producer is not unmasked yet
+        let (producer, rx) = unsafe { RX_QUEUE.split() };
+        unsafe { RX_PRODUCER = Some(producer) };
+
+        // RXNEIE: Generate an interrupt whenever RXNE (or ORE) is set
+        // PEIE: Also generate it on a parity error
+        usart1.cr1.modify(|_, w| w.rxneie().set_bit().peie().set_bit());
+        // EIE: And on framing errors and noise
+        usart1.cr3.modify(|_, w| w.eie().set_bit());
+        unsafe { NVIC::unmask(Interrupt::USART1_EXTI25) };
+
+        SerialPort {
+            usart1,
+            rx,
+            tx: TxQueue::new(),
+        }
+    }
+
+    fn dequeue(&mut self) -> Option<Received> {
+        let received = self.rx.dequeue();
+
+        // The interrupt handler stopped reading RDR to hold the host back, there is room again
+        if self.rx.len() <= RX_LOW_WATER && self.usart1.cr1.read().rxneie().bit_is_clear() {
+            listen_rx(self.usart1, true);
+        }
+
+        received
+    }
+
+    /// Returns the oldest received byte, or `None` if nothing has arrived
+    ///
+    /// Receive errors are skipped, they only show up in `error_counts`
+    pub fn try_read(&mut self) -> Option<u8> {
+        loop {
+            match self.dequeue()? {
+                Received::Byte(byte) => return Some(byte),
+                Received::Error(_) => continue,
+            }
+        }
+    }
+
+    /// Moves as many received bytes as are available (up to `buf.len()`) into `buf` without
+    /// blocking, returns the number of bytes that were read
+    ///
+    /// It stops before a receive error, so that the next `read_byte` reports it
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let mut n = 0;
+        while n < buf.len() {
+            match self.rx.peek() {
+                Some(&Received::Byte(byte)) => buf[n] = byte,
+                _ => break,
+            }
+            self.dequeue();
+            n += 1;
+        }
+        n
+    }
+
+    /// Blocks until a byte is available
+    ///
+    /// Errors the USART flagged are returned in the order they happened: the bytes that arrived
+    /// before an error are read first, and each error is only returned once. The flags themselves
+    /// have already been cleared through ICR by the interrupt handler, so reception goes on after
+    /// an error
+    pub fn read_byte(&mut self) -> Result<u8, SerialError> {
+        loop {
+            if let Some(byte) = self.try_read_byte()? {
+                return Ok(byte);
+            }
+        }
+    }
+
+    /// Like `read_byte`, but gives up with `SerialError::Timeout` if nothing arrives within
+    /// `timeout`
+    pub fn read_byte_timeout(
+        &mut self,
+        timer: MonoTimer,
+        timeout: Duration,
+    ) -> Result<u8, SerialError> {
+        let start = timer.now();
+        let ticks = timer.ticks(timeout);
+
+        loop {
+            if let Some(byte) = self.try_read_byte()? {
+                return Ok(byte);
+            }
+
+            if start.elapsed() >= ticks {
+                return Err(SerialError::Timeout);
+            }
+        }
+    }
+
+    /// Reads bytes into `buf` until a line ending (LF, a CR before it is dropped) and returns the
+    /// length of the line, without the line ending
+    ///
+    /// `timeout` is for the whole line, on `SerialError::Timeout` (or any other error) the bytes
+    /// read so far are lost. If the line doesn't fit in `buf` the first `buf.len()` bytes are
+    /// returned, and the rest of the line is left for the next read
+    pub fn read_line_timeout(
+        &mut self,
+        buf: &mut [u8],
+        timer: MonoTimer,
+        timeout: Duration,
+    ) -> Result<usize, SerialError> {
+        let start = timer.now();
+        let ticks = timer.ticks(timeout);
+
+        let mut n = 0;
+        while n < buf.len() {
+            let byte = match self.try_read_byte()? {
+                Some(byte) => byte,
+                None if start.elapsed() >= ticks => return Err(SerialError::Timeout),
+                None => continue,
+            };
+
+            if byte == b'\n' {
+                if n > 0 && buf[n - 1] == b'\r' {
+                    n -= 1;
+                }
+                break;
+            }
+
+            buf[n] = byte;
+            n += 1;
+        }
+
+        Ok(n)
+    }
+
+    /// `read_byte` without the waiting, `Ok(None)` if nothing has arrived
+    pub fn try_read_byte(&mut self) -> Result<Option<u8>, SerialError> {
+        match self.dequeue() {
+            Some(Received::Byte(byte)) => Ok(Some(byte)),
+            Some(Received::Error(error)) => Err(error),
+            None => Ok(None),
+        }
+    }
+
+    /// `read_byte` for `async` code, waits for a byte without blocking other tasks
+    pub async fn read_byte_async(&mut self) -> Result<u8, SerialError> {
+        RX_SIGNAL.wait(|| self.try_read_byte().transpose()).await
+    }
+
+    /// Number of bytes dropped so far because the receive buffer was full
+    pub fn overflows(&self) -> u32 {
+        RX_OVERFLOWS.load(Ordering::Relaxed)
+    }
+
+    /// Number of receive errors of each kind seen so far
+    pub fn error_counts(&self) -> ErrorCounts {
+        let count = |error: SerialError| RX_ERRORS[error as usize].load(Ordering::Relaxed);
+
+        ErrorCounts {
+            overrun: count(SerialError::Overrun),
+            framing: count(SerialError::Framing),
+            noise: count(SerialError::Noise),
+            parity: count(SerialError::Parity),
+        }
+    }
+
+    /// Switches to a new baud rate once everything written so far has been transmitted
+    ///
+    /// A rate that can't be generated from PCLK2 is rejected right away, see `check_baud_rate`
+    pub fn set_baud(&mut self, baud_rate: u32) -> Result<(), InvalidBaudRate> {
+        check_baud_rate(baud_rate)?;
+
+        self.flush();
+        set_baud_rate(self.usart1, baud_rate)
+    }
+
+    /// The baud rate currently in use
+    pub fn baud(&self) -> u32 {
+        baud_rate(self.usart1)
+    }
+
+    /// Queues raw bytes for transmission, unlike `write_str` they don't have to be UTF-8
+    ///
+    /// With hardware flow control the USART holds the bytes back while CTS is high
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.tx.write(bytes);
+    }
+
+    /// Blocks until everything written so far has been transmitted
+    pub fn flush(&mut self) {
+        self.tx.flush();
+    }
+
+    /// `write_bytes` for `async` code, waits for room without blocking other tasks
+    pub async fn write_async(&mut self, bytes: &[u8]) {
+        self.tx.write_async(bytes).await;
+    }
+
+    /// `flush` for `async` code
+    pub async fn flush_async(&mut self) {
+        self.tx.flush_async().await;
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Returns as soon as the bytes have been copied, DMA1 moves them to TDR
+        self.tx.write(s.as_bytes());
+
+        Ok(())
+    }
+}
+
+impl embedded_hal::serial::Read<u8> for SerialPort {
+    type Error = SerialError;
+
+    fn read(&mut self) -> nb::Result<u8, SerialError> {
+        self.try_read_byte()?.ok_or(nb::Error::WouldBlock)
+    }
+}
+
+impl embedded_hal::serial::Write<u8> for SerialPort {
+    type Error = Infallible;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Infallible> {
+        match self.tx.try_write(&[word]) {
+            0 => Err(nb::Error::WouldBlock),
+            _ => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Infallible> {
+        if self.tx.is_idle() && self.usart1.isr.read().tc().bit_is_set() {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl embedded_hal::blocking::serial::write::Default<u8> for SerialPort {}
+
+impl embedded_io::Error for SerialError {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            SerialError::Overrun => embedded_io::ErrorKind::Other,
+            SerialError::Timeout => embedded_io::ErrorKind::TimedOut,
+            SerialError::Framing | SerialError::Noise | SerialError::Parity => {
+                embedded_io::ErrorKind::InvalidData
+            }
+        }
+    }
+}
+
+impl embedded_io::ErrorType for SerialPort {
+    type Error = SerialError;
+}
+
+impl embedded_io::Read for SerialPort {
+    /// Blocks until at least one byte is available
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, SerialError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        buf[0] = self.read_byte()?;
+        Ok(1 + SerialPort::read(self, &mut buf[1..]))
+    }
+}
+
+impl embedded_io::ReadReady for SerialPort {
+    fn read_ready(&mut self) -> Result<bool, SerialError> {
+        Ok(self.rx.ready())
+    }
+}
+
+impl embedded_io::Write for SerialPort {
+    /// Blocks until at least one byte has been queued
+    fn write(&mut self, buf: &[u8]) -> Result<usize, SerialError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            match self.tx.try_write(buf) {
+                0 => continue,
+                n => return Ok(n),
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), SerialError> {
+        SerialPort::flush(self);
+        Ok(())
+    }
+}
+
+impl embedded_io::WriteReady for SerialPort {
+    fn write_ready(&mut self) -> Result<bool, SerialError> {
+        Ok(self.tx.has_room())
+    }
+}
+
+/// Must be called from the USART1_EXTI25 interrupt handler
+pub fn on_interrupt() {
+    // NOTE(unsafe) the `SerialPort` only touches RDR through this interrupt
+    let usart1 = unsafe { &*USART1::ptr() };
+    let isr = usart1.isr.read();
+
+    // Waiting tasks only run after we return, by then the byte (or error) has been recorded
+    RX_SIGNAL.wake();
+
+    // An uncleared ORE keeps RXNE from ever being set again, and any of these flags left set would
+    // bring us right back here
+    usart1.icr.write(|w| {
+        w.orecf().set_bit();
+        w.fecf().set_bit();
+        w.ncf().set_bit();
+        w.pecf().set_bit()
+    });
+
+    // NOTE(unsafe) only this interrupt handler uses the producer
+    let producer = match unsafe { RX_PRODUCER.as_mut() } {
+        Some(producer) => producer,
+        None => return,
+    };
+
+    if isr.rxne().bit_is_set() {
+        // Reading RDR clears RXNE
+        let byte = usart1.rdr.read().rdr().bits() as u8;
+
+        // On ORE the byte in RDR is still good (the one after it was lost), and on NF it is the
+        // majority vote of the samples (RM0316, noise error), but with FE or PE the byte itself
+        // is suspect
+        let suspect = isr.fe().bit_is_set() || isr.pe().bit_is_set();
+        if !suspect && producer.enqueue(Received::Byte(byte)).is_err() {
+            RX_OVERFLOWS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // After the byte: the byte lost to ORE came after the one in RDR
+    let errors = [
+        (SerialError::Overrun, isr.ore().bit_is_set()),
+        (SerialError::Framing, isr.fe().bit_is_set()),
+        (SerialError::Noise, isr.nf().bit_is_set()),
+        (SerialError::Parity, isr.pe().bit_is_set()),
+    ];
+    for &(error, flagged) in &errors {
+        if flagged {
+            RX_ERRORS[error as usize].fetch_add(1, Ordering::Relaxed);
+            // With the buffer full the error is only counted. Noise is only counted, the byte
+            // went through
+            if error != SerialError::Noise {
+                producer.enqueue(Received::Error(error)).ok();
+            }
+        }
+    }
+
+    // Leave the next byte in RDR, the USART keeps RTS high until `SerialPort` makes room. Without
+    // flow control the host would just overrun RDR, so keep going
+    if producer.len() >= RX_HIGH_WATER && flow_control(usart1) == FlowControl::RtsCts {
+        usart1.cr1.modify(|_, w| w.rxneie().clear_bit());
+    }
+}
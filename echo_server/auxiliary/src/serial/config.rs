@@ -0,0 +1,268 @@
+//! USART1 frame format, baud rate and flow control configuration
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use cortex_m::interrupt;
+use stm32f3_discovery::stm32f3xx_hal::pac::usart1;
+
+// Frequency of the clock that drives USART1 (PCLK2), set by `init`
+static USART1_CLOCK: AtomicU32 = AtomicU32::new(0);
+
+// With oversampling by 16 BRR must be at least 16, and it is 16 bits wide
+const MIN_BRR: u32 = 16;
+const MAX_BRR: u32 = 0xFFFF;
+
+/// Serial port settings passed to `aux11::init`
+#[derive(Clone, Copy, Debug)]
+pub struct SerialConfig {
+    pub baud_rate: u32,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub word_length: WordLength,
+    /// Measure the baud rate of the first byte received instead of using `baud_rate`
+    pub auto_baud: Option<AutoBaudMode>,
+    pub flow_control: FlowControl,
+}
+
+impl Default for SerialConfig {
+    /// 9600 8N1
+    fn default() -> Self {
+        SerialConfig {
+            baud_rate: 9600,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            word_length: WordLength::DataBits8,
+            auto_baud: None,
+            flow_control: FlowControl::None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Half,
+    Two,
+    OneAndHalf,
+}
+
+/// Hardware flow control, CTS is PA11 and RTS is PA12 (USART1 has no other pins for them)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowControl {
+    None,
+    /// The USART only transmits while CTS is low and drives RTS high while RDR is full, so the
+    /// host stops sending before a byte gets overrun
+    RtsCts,
+}
+
+/// Number of data bits, not counting the parity bit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WordLength {
+    DataBits7,
+    DataBits8,
+}
+
+/// What the USART measures to find out the baud rate (ABRMOD)
+///
+/// The host has to send a byte that fits the mode first, e.g. `0x7F` for `Frame7F` or `U`
+/// for `Frame55`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AutoBaudMode {
+    /// The length of the start bit, the first byte must start with a 1 bit
+    StartBit,
+    /// Falling edge to falling edge, the first byte must start with the bit pattern 10xx
+    FallingEdge,
+    /// The whole 0x7F frame
+    Frame7F,
+    /// The whole 0x55 frame, the most robust one
+    Frame55,
+}
+
+/// A baud rate that USART1 can't generate from its clock
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidBaudRate {
+    /// Lowest baud rate that works with the current clock
+    pub min: u32,
+    /// Highest baud rate that works with the current clock
+    pub max: u32,
+}
+
+impl fmt::Display for InvalidBaudRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "baud rate out of range ({} to {} Bd)",
+            self.min, self.max
+        )
+    }
+}
+
+/// Applies `config` to USART1, which must be disabled (UE cleared)
+pub(crate) fn configure(
+    usart1: &usart1::RegisterBlock,
+    clock: u32,
+    config: &SerialConfig,
+) -> Result<(), InvalidBaudRate> {
+    USART1_CLOCK.store(clock, Ordering::Relaxed);
+    let brr = brr(config.baud_rate)?;
+
+    // The word length includes the parity bit, M1:M0 = 00 -> 8 bits, 01 -> 9 bits, 10 -> 7 bits
+    let parity = config.parity != Parity::None;
+    let (m1, m0) = match (config.word_length, parity) {
+        (WordLength::DataBits7, false) => (true, false),
+        (WordLength::DataBits7, true) | (WordLength::DataBits8, false) => (false, false),
+        (WordLength::DataBits8, true) => (false, true),
+    };
+    usart1.cr1.modify(|_, w| {
+        w.m1().bit(m1);
+        w.m0().bit(m0);
+        w.pce().bit(parity);
+        // PS: 0 -> even, 1 -> odd
+        w.ps().bit(config.parity == Parity::Odd)
+    });
+
+    let stop = match config.stop_bits {
+        StopBits::One => 0b00,
+        StopBits::Half => 0b01,
+        StopBits::Two => 0b10,
+        StopBits::OneAndHalf => 0b11,
+    };
+    usart1.cr2.modify(|_, w| unsafe {
+        w.stop().bits(stop);
+        match config.auto_baud {
+            Some(mode) => {
+                let abrmod = match mode {
+                    AutoBaudMode::StartBit => 0b00,
+                    AutoBaudMode::FallingEdge => 0b01,
+                    AutoBaudMode::Frame7F => 0b10,
+                    AutoBaudMode::Frame55 => 0b11,
+                };
+                w.abrmod().bits(abrmod).abren().set_bit()
+            }
+            None => w.abren().clear_bit(),
+        }
+    });
+
+    let flow_control = config.flow_control == FlowControl::RtsCts;
+    usart1
+        .cr3
+        .modify(|_, w| w.rtse().bit(flow_control).ctse().bit(flow_control));
+
+    usart1.brr.write(|w| w.brr().bits(brr));
+
+    Ok(())
+}
+
+/// Is hardware flow control enabled?
+pub fn flow_control(usart1: &usart1::RegisterBlock) -> FlowControl {
+    if usart1.cr3.read().rtse().bit_is_set() {
+        FlowControl::RtsCts
+    } else {
+        FlowControl::None
+    }
+}
+
+/// Is the other end ready to receive? Always `true` without flow control
+pub fn clear_to_send(usart1: &usart1::RegisterBlock) -> bool {
+    // The CTS flag mirrors the inverted CTS line, it is set while CTS is low
+    flow_control(usart1) == FlowControl::None || usart1.isr.read().cts().bit_is_set()
+}
+
+/// Enables or disables the RXNE interrupt
+///
+/// With hardware flow control this is how a receiver running out of buffer space pushes back:
+/// with the interrupt off RDR stays full, so the USART drives RTS high until the interrupt is
+/// enabled again
+pub fn listen_rx(usart1: &usart1::RegisterBlock, enabled: bool) {
+    // The interrupt handler may modify CR1 too
+    interrupt::free(|_| usart1.cr1.modify(|_, w| w.rxneie().bit(enabled)));
+}
+
+/// Returns an error if USART1 can't run at `baud_rate` with its current clock (PCLK2)
+pub fn check_baud_rate(baud_rate: u32) -> Result<(), InvalidBaudRate> {
+    brr(baud_rate).map(drop)
+}
+
+/// Changes the baud rate of USART1, nothing changes if the rate is out of range
+///
+/// Anything still being transmitted is cut short, flush first
+pub fn set_baud_rate(
+    usart1: &usart1::RegisterBlock,
+    baud_rate: u32,
+) -> Result<(), InvalidBaudRate> {
+    let brr = brr(baud_rate)?;
+
+    // BRR can only be written while the USART is disabled
+    usart1.cr1.modify(|_, w| w.ue().clear_bit());
+    usart1.brr.write(|w| w.brr().bits(brr));
+    usart1.cr1.modify(|_, w| w.ue().set_bit());
+
+    Ok(())
+}
+
+/// The baud rate USART1 is currently using, with auto baud rate detection this is whatever the
+/// hardware measured
+pub fn baud_rate(usart1: &usart1::RegisterBlock) -> u32 {
+    let brr = u32::from(usart1.brr.read().brr().bits());
+    if brr == 0 {
+        return 0;
+    }
+
+    USART1_CLOCK.load(Ordering::Relaxed) / brr
+}
+
+/// State of the auto baud rate detection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AutoBaudStatus {
+    /// Still waiting for the first byte
+    Pending,
+    /// The baud rate has been measured
+    Done(u32),
+    /// The byte received didn't fit the mode, or the rate is out of range
+    Failed,
+}
+
+pub fn auto_baud_status(usart1: &usart1::RegisterBlock) -> AutoBaudStatus {
+    let isr = usart1.isr.read();
+
+    if isr.abre().bit_is_set() {
+        AutoBaudStatus::Failed
+    } else if isr.abrf().bit_is_set() {
+        AutoBaudStatus::Done(baud_rate(usart1))
+    } else {
+        AutoBaudStatus::Pending
+    }
+}
+
+/// Measures the baud rate again on the next byte received, only if auto baud rate detection was
+/// enabled by `init`
+pub fn restart_auto_baud(usart1: &usart1::RegisterBlock) {
+    // ABRRQ: Clears ABRF / ABRE and starts a new measurement
+    usart1.rqr.write(|w| w.abrrq().set_bit());
+}
+
+// Oversampling by 16: BRR = f_CK / baud rate, rounded to the nearest integer
+fn brr(baud_rate: u32) -> Result<u16, InvalidBaudRate> {
+    let clock = USART1_CLOCK.load(Ordering::Relaxed);
+    let range = InvalidBaudRate {
+        // At least 1 Bd, so that there is no division by zero even before `init`
+        min: ((clock + MAX_BRR - 1) / MAX_BRR).max(1),
+        max: clock / MIN_BRR,
+    };
+
+    if !(range.min..=range.max).contains(&baud_rate) {
+        return Err(range);
+    }
+
+    Ok(((clock + baud_rate / 2) / baud_rate) as u16)
+}
@@ -0,0 +1,72 @@
+//! The echo server written with `async` tasks instead of the scheduler
+//!
+//! One task echoes every byte it receives, the other one blinks the north LED. Neither blocks
+//! the other, and the core sleeps while both are waiting.
+
+#![no_main]
+#![no_std]
+
+use core::pin::pin;
+
+use aux11::{
+    entry, exception, interrupt,
+    serial::{SerialConfig, SerialPort},
+    time::{self, Timer},
+    ClockProfile, Direction, LedPwm,
+};
+
+// Half the blink period, in ms
+const BLINK: u32 = 500;
+
+async fn echo(serial: &mut SerialPort) {
+    loop {
+        // Receive errors are counted by `SerialPort`, the byte that came with one is lost
+        if let Ok(byte) = serial.read_byte_async().await {
+            serial.write_async(&[byte]).await;
+        }
+    }
+}
+
+async fn blink(leds: &mut LedPwm) {
+    loop {
+        let off = leds.brightness(Direction::N) == 0;
+        leds.set_brightness(Direction::N, if off { 255 } else { 0 });
+        Timer::after(BLINK).await;
+    }
+}
+
+#[entry]
+fn main() -> ! {
+    let (usart1, _mono_timer, _itm, mut leds, _magnetometer) =
+        aux11::init(ClockProfile::PLL_64MHZ, SerialConfig::default());
+
+    let mut serial = SerialPort::new(usart1);
+    time::start();
+
+    executor::run(
+        &mut [pin!(echo(&mut serial)), pin!(blink(&mut leds))],
+        aux11::idle,
+    );
+
+    unreachable!("the tasks never return");
+}
+
+#[exception]
+fn SysTick() {
+    aux11::monotimer::on_systick();
+}
+
+#[interrupt]
+fn DMA1_CH4() {
+    aux11::dma::on_transfer_complete();
+}
+
+#[interrupt]
+fn USART1_EXTI25() {
+    aux11::serial::on_interrupt();
+}
+
+#[interrupt]
+fn TIM6_DACUNDER() {
+    time::on_interrupt();
+}
@@ -0,0 +1,110 @@
+//! Multi-line text entry, used by `leds load` to take a script longer than a shell line
+//!
+//! Bytes are fed to `Editor::feed` one at a time, like `Shell::feed`. They are echoed back and
+//! collected line by line until a line with only `end` on it; backspace / DEL only erase within
+//! the current line and Ctrl-C drops everything. The `end` line is not part of the text.
+
+use core::{fmt, mem};
+
+use heapless::String;
+
+// Erases what is on the line first, e.g. the prompt of the shell
+const PROMPT: &str = "\r\x1b[K. ";
+const END: &str = "end";
+
+// ASCII control characters
+const CTRL_C: u8 = 0x03;
+const BACKSPACE: u8 = 0x08;
+const DEL: u8 = 0x7f;
+
+/// Where the text entry is at, after a byte
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// More lines to come
+    Editing,
+    /// The `end` line was entered, `text` is complete
+    Done,
+    /// Ctrl-C was pressed, the text is to be dropped
+    Cancelled,
+}
+
+/// Collects up to `N` bytes of text
+pub struct Editor<const N: usize> {
+    text: String<N>,
+    // Where the line being edited starts in `text`
+    line_start: usize,
+    // Set when a byte didn't fit, the text is incomplete
+    overflow: bool,
+    // Set after a CR, so that the LF of a CRLF doesn't end an empty line
+    skip_lf: bool,
+}
+
+impl<const N: usize> Default for Editor<N> {
+    fn default() -> Self {
+        Editor {
+            text: String::new(),
+            line_start: 0,
+            overflow: false,
+            skip_lf: false,
+        }
+    }
+}
+
+impl<const N: usize> Editor<N> {
+    pub fn prompt<W: fmt::Write>(&self, out: &mut W) {
+        uprint!(out, "{}", PROMPT);
+    }
+
+    /// Handles a byte received from the terminal
+    pub fn feed<W: fmt::Write>(&mut self, byte: u8, out: &mut W) -> Status {
+        let skip_lf = mem::replace(&mut self.skip_lf, false);
+
+        match byte {
+            b'\n' if skip_lf => {}
+            b'\r' | b'\n' => {
+                self.skip_lf = byte == b'\r';
+                uprintln!(out, "");
+
+                if self.text[self.line_start..].trim() == END {
+                    self.text.truncate(self.line_start);
+                    return Status::Done;
+                }
+
+                if self.text.push('\n').is_err() {
+                    self.overflow = true;
+                }
+                self.line_start = self.text.len();
+                self.prompt(out);
+            }
+            BACKSPACE | DEL if self.text.len() > self.line_start => {
+                self.text.pop();
+                uprint!(out, "\x08 \x08");
+            }
+            CTRL_C => {
+                uprintln!(out, "^C");
+                return Status::Cancelled;
+            }
+            // Scripts are plain ASCII, other control characters and escape sequences are ignored
+            b' '..=b'~' => {
+                if self.text.push(byte as char).is_ok() {
+                    uprint!(out, "{}", byte as char);
+                } else {
+                    self.overflow = true;
+                }
+            }
+            _ => {}
+        }
+
+        Status::Editing
+    }
+
+    /// The text entered so far, lines separated by `\n`
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns `true` if some of the text didn't fit and was dropped
+    pub fn overflowed(&self) -> bool {
+        self.overflow
+    }
+}
@@ -1,10 +1,25 @@
 #![no_main]
 #![no_std]
 
-use core::fmt::{self, Write};
+use core::{
+    fmt::{self, Write},
+    time::Duration,
+};
 
-use aux11::{entry, usart1, iprintln};
-use heapless::Vec;
+use aux11::{
+    animation::Keyframes,
+    entry, exception,
+    framing::{Crc16, FrameDecoder, FrameEncoder, HardwareCrc, MAX_ENCODED_LEN, MAX_PAYLOAD},
+    interrupt, iprintln,
+    magnetometer::{Magnetometer, Nack},
+    monotimer::{Instant, MonoTimer},
+    profile, script,
+    serial::{check_baud_rate, SerialConfig, SerialPort},
+    Animation, ClockProfile, LedPwm, Pattern, ITM,
+};
+use heapless::String;
+use protocol::message::{Message, Sample, MAX_TEXT};
+use scheduler::{Schedule, Scheduler, Task};
 
 macro_rules! uprint {
     ($serial:expr, $($arg:tt)*) => {
@@ -21,34 +36,98 @@ macro_rules! uprintln {
     };
 }
 
-struct SerialPort {
-    usart1: &'static mut usart1::RegisterBlock,
-}
+mod editor;
+mod shell;
 
-impl SerialPort {
-    pub fn read_byte(&self) -> u8 {
-        while self.usart1.isr.read().rxne().bit_is_clear() {}
-        self.usart1.rdr.read().rdr().bits() as u8
-    }
+use editor::{Editor, Status};
+use shell::{Args, Command, OverflowPolicy, Shell};
+use text::reverse;
+
+// Maximum length of a shell line, in bytes. Long enough for a short LED script
+const LINE_LENGTH: usize = 64;
+// Maximum length of a script entered with `leds load`, in bytes
+const SCRIPT_LENGTH: usize = 512;
+// How much output of a command run in framed mode is kept, the rest is dropped
+const CAPTURE_LENGTH: usize = 256;
+// In framed mode, a frame that is still incomplete this long after its first byte arrived is
+// dropped, the host is not going to finish it
+const FRAME_TIMEOUT: Duration = Duration::from_secs(1);
+
+// The shell context: the serial port plus what the command handlers need besides it
+struct Console {
+    serial: SerialPort,
+    timer: MonoTimer,
+    itm: ITM,
+    // When set, text written with `write_str` is collected here instead of being sent
+    capture: Option<String<CAPTURE_LENGTH>>,
+    // Set by the `framed` command, `poll_serial` takes frames instead of lines while it is set
+    framed: Option<Framed>,
+    // Set by the `stream` command, magnetometer samples are sent to the host in framed mode
+    streaming: bool,
+    // What the LEDs show, changed by the `leds` command
+    animation: Animation,
+    // Set by `leds load`, the received bytes go to the editor instead of the shell until the
+    // script is complete
+    editor: Option<Editor<SCRIPT_LENGTH>>,
+    // Latest magnetometer reading, `None` until the first one
+    reading: Option<Result<(i16, i16, i16), Nack>>,
 }
 
-impl fmt::Write for SerialPort {
+impl fmt::Write for Console {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for byte in s.bytes() {
-            // Wait until its safe to write to TDR
-            while self.usart1.isr.read().txe().bit_is_clear() {}
-
-            // Write a byte
-            self.usart1.tdr.write(|w| w.tdr().bits(byte as u16));
+        if let Some(capture) = &mut self.capture {
+            // Output that doesn't fit is dropped
+            capture.push_str(s).ok();
+            return Ok(());
         }
 
-        Ok(())
+        self.serial.write_str(s)
     }
 }
 
+// Framed mode, used by the host CLI: every `Message::Command` received is run by the shell, its
+// output is sent back as `Message::Reply`s followed by a `Message::Done`
+struct Framed {
+    decoder: FrameDecoder<HardwareCrc>,
+    encoder: FrameEncoder<HardwareCrc>,
+    // When the first byte of the frame being received arrived, see `FRAME_TIMEOUT`
+    frame_start: Option<Instant>,
+}
+
+// Everything the tasks work on
+struct App {
+    console: Console,
+    shell: Shell<Console, LINE_LENGTH>,
+    leds: LedPwm,
+    magnetometer: Magnetometer,
+}
+
+// The shell, the LED animation and the magnetometer run side by side
+static TASKS: &[Task<App>] = &[
+    Task {
+        name: "serial",
+        priority: 2,
+        schedule: Schedule::Always,
+        run: poll_serial,
+    },
+    Task {
+        name: "leds",
+        priority: 1,
+        schedule: Schedule::Every(10),
+        run: animate,
+    },
+    Task {
+        name: "magnetometer",
+        priority: 1,
+        schedule: Schedule::Every(50),
+        run: sample,
+    },
+];
+
 #[entry]
 fn main() -> ! {
-    let (usart1, _mono_timer, mut itm) = aux11::init();
+    let (usart1, mono_timer, itm, leds, magnetometer) =
+        aux11::init(ClockProfile::HSI_8MHZ, SerialConfig::default());
 
     // Echo server
     // loop {
@@ -61,33 +140,481 @@ fn main() -> ! {
     //     usart1.tdr.write(|w| w.tdr().bits(byte as u16));
     // }
 
-    // Respond to the client with the reverse of the text they sent. The server responds each time
-    // they press the enter key.
-    let mut serial = SerialPort { usart1 };
-    let mut buffer: Vec<u8, 32> = Vec::new();
+    // Interactive shell, type `help` in the terminal to list the commands
+    let mut app = App {
+        console: Console {
+            serial: SerialPort::new(usart1),
+            timer: mono_timer,
+            itm,
+            capture: None,
+            framed: None,
+            streaming: false,
+            animation: Animation::new(roulette()),
+            editor: None,
+            reading: None,
+        },
+        shell: Shell::new(COMMANDS, OverflowPolicy::DiscardLine),
+        leds,
+        magnetometer,
+    };
+    if let Err(error) = app.magnetometer.enable() {
+        uprintln!(app.console, "Error: {}", error);
+    }
+    app.shell.prompt(&mut app.console);
+
+    let now = || mono_timer.now().as_millis(mono_timer);
+    let mut scheduler: Scheduler<App, 4> = Scheduler::new();
+    for task in TASKS {
+        scheduler.spawn(*task, now()).ok();
+    }
+
+    loop {
+        scheduler.poll(now(), &mut app);
+    }
+}
+
+// Feeds whatever has been received to the shell
+fn poll_serial(app: &mut App) {
+    let console = &mut app.console;
+
+    // Out of `console` while the commands it receives run
+    if let Some(mut framed) = console.framed.take() {
+        if poll_framed(console, &app.shell, &mut framed) {
+            console.framed = Some(framed);
+        } else {
+            console.streaming = false;
+            uprintln!(console, "Back to the shell");
+            app.shell.prompt(console);
+        }
+        return;
+    }
 
     loop {
-        buffer.clear();
+        match console.serial.try_read_byte() {
+            Ok(Some(byte)) => {
+                iprintln!(&mut console.itm.stim[0], "{} ({})", byte as char, byte);
+
+                match &mut console.editor {
+                    Some(editor) => match editor.feed(byte, &mut console.serial) {
+                        Status::Editing => {}
+                        Status::Done => {
+                            if let Some(editor) = console.editor.take() {
+                                if editor.overflowed() {
+                                    uprintln!(
+                                        console,
+                                        "Error: script too long ({} bytes max)",
+                                        SCRIPT_LENGTH
+                                    );
+                                } else {
+                                    load_script(console, editor.text());
+                                }
+                            }
+                            app.shell.prompt(console);
+                        }
+                        Status::Cancelled => {
+                            console.editor = None;
+                            app.shell.prompt(console);
+                        }
+                    },
+                    None => {
+                        app.shell.feed(byte, console);
 
-        let mut byte = 0;
-        while byte != '\n' as u8 {
-            byte = serial.read_byte();
+                        // `leds load` was run, the prompt of the editor replaces the shell's
+                        if let Some(editor) = &console.editor {
+                            editor.prompt(&mut console.serial);
+                        }
 
-            match buffer.push(byte) {
-                Ok(_) => (),
-                Err(byte) => {
-                    uprintln!(serial, "Error: buffer is full, cannot write {}", byte);
-                    continue;
+                        // `framed` was run, the next bytes are frames
+                        if console.framed.is_some() {
+                            break;
+                        }
+                    }
                 }
             }
-            iprintln!(&mut itm.stim[0], "{} ({})\n{:?}", byte as char, byte, buffer);
+            Ok(None) => break,
+            Err(error) => {
+                // Part of the line is missing or garbled, there is no point in running it
+                let counts = console.serial.error_counts();
+                uprintln!(console, "\nError: {}, line discarded ({:?})", error, counts);
+                match &console.editor {
+                    Some(editor) => editor.prompt(&mut console.serial),
+                    None => app.shell.cancel(console),
+                }
+            }
+        }
+    }
+}
+
+// Shows the next frame of the animation
+fn animate(app: &mut App) {
+    let _profile = profile!("animate");
+    let timer = app.console.timer;
+    let frame = app.console.animation.tick(timer.now().as_millis(timer));
+
+    app.leds.show(&frame);
+}
+
+// Keeps the latest magnetometer reading for the `mag` command, and streams it to the host
+fn sample(app: &mut App) {
+    let _profile = profile!("sample");
+    let reading = app.magnetometer.read();
+    app.console.reading = Some(reading);
+
+    let console = &mut app.console;
+    let framed = match &mut console.framed {
+        Some(framed) if console.streaming => framed,
+        _ => return,
+    };
+
+    if let Ok((x, y, z)) = reading {
+        let sample = Sample {
+            // Wraps after about 71 minutes, the host only looks at the differences
+            timestamp_us: console.timer.now().as_micros(console.timer) as u32,
+            x,
+            y,
+            z,
+        };
+        send_message(
+            &mut console.serial,
+            &mut framed.encoder,
+            Message::Sample(sample),
+        );
+    }
+}
+
+// Shown at startup and by `leds roulette`
+fn roulette() -> Pattern {
+    Pattern::Keyframes(Keyframes::roulette())
+}
+
+static COMMANDS: &[Command<Console>] = &[
+    Command {
+        name: "echo",
+        help: "echo <words..>: print the arguments",
+        handler: echo,
+    },
+    Command {
+        name: "reverse",
+        help: "reverse <text>: print the text backwards",
+        handler: reverse,
+    },
+    Command {
+        name: "errors",
+        help: "errors: show the receive error counters",
+        handler: errors,
+    },
+    Command {
+        name: "baud",
+        help: "baud [rate]: show or change the baud rate",
+        handler: baud,
+    },
+    Command {
+        name: "profile",
+        help: "profile [itm|reset]: show the profiled sections, over ITM, or clear them",
+        handler: profile,
+    },
+    Command {
+        name: "framed",
+        help: "framed: take commands as COBS frames until an empty one arrives",
+        handler: framed,
+    },
+    Command {
+        name: "stream",
+        help: "stream [on|off]: send magnetometer samples to the host in framed mode",
+        handler: stream,
+    },
+    Command {
+        name: "mag",
+        help: "mag: show the latest magnetometer reading",
+        handler: mag,
+    },
+    Command {
+        name: "leds",
+        help: "leds [<script>|load|<pattern>|speed <%>|reverse]: show or change the LED animation",
+        handler: leds,
+    },
+];
+
+fn echo(console: &mut Console, args: &Args) -> Result<(), &'static str> {
+    for arg in args.iter() {
+        uprint!(console, "{} ", arg);
+    }
+    uprintln!(console, "");
+
+    Ok(())
+}
+
+// Respond with the reverse of the text that was sent
+fn reverse(console: &mut Console, args: &Args) -> Result<(), &'static str> {
+    for cluster in reverse::reverse(args.rest()) {
+        uprint!(console, "{}", cluster);
+    }
+    uprintln!(console, "");
+
+    Ok(())
+}
+
+fn errors(console: &mut Console, _args: &Args) -> Result<(), &'static str> {
+    let counts = console.serial.error_counts();
+    let overflows = console.serial.overflows();
+    uprintln!(console, "{:?}, {} bytes dropped (buffer full)", counts, overflows);
+
+    Ok(())
+}
+
+fn baud(console: &mut Console, args: &Args) -> Result<(), &'static str> {
+    if args.is_empty() {
+        uprintln!(console, "{} Bd", console.serial.baud());
+        return Ok(());
+    }
+
+    let baud_rate: u32 = args.parse(0)?;
+    // Depends on PCLK2, e.g. 123 Bd to 500 kBd at 8 MHz
+    if let Err(error) = check_baud_rate(baud_rate) {
+        uprintln!(console, "Error: {}", error);
+        return Ok(());
+    }
+
+    uprintln!(console, "Switching to {} Bd", baud_rate);
+    console.serial.set_baud(baud_rate).ok();
+
+    Ok(())
+}
+
+fn profile(console: &mut Console, args: &Args) -> Result<(), &'static str> {
+    let frequency = console.timer.frequency().0;
+
+    match args.get(0) {
+        None => {
+            profile::report(console, frequency).ok();
+        }
+        Some("itm") => profile::report_itm(&mut console.itm.stim[0], frequency),
+        Some("reset") => profile::reset(),
+        Some(_) => return Err("invalid argument"),
+    }
+
+    Ok(())
+}
+
+fn framed(console: &mut Console, _args: &Args) -> Result<(), &'static str> {
+    if console.capture.is_some() {
+        return Err("already in framed mode");
+    }
+
+    uprintln!(console, "Framed mode, send an empty frame to go back to the shell");
+    console.framed = Some(Framed {
+        decoder: FrameDecoder::new(HardwareCrc::new()),
+        encoder: FrameEncoder::new(HardwareCrc::new()),
+        frame_start: None,
+    });
+
+    Ok(())
+}
+
+fn stream(console: &mut Console, args: &Args) -> Result<(), &'static str> {
+    match args.get(0) {
+        None => {
+            let state = if console.streaming { "on" } else { "off" };
+            uprintln!(console, "Streaming {}", state);
+        }
+        Some("on") => console.streaming = true,
+        Some("off") => console.streaming = false,
+        Some(_) => return Err("invalid argument"),
+    }
+
+    Ok(())
+}
+
+fn mag(console: &mut Console, _args: &Args) -> Result<(), &'static str> {
+    match console.reading {
+        Some(Ok((x, y, z))) => uprintln!(console, "x: {}, y: {}, z: {}", x, y, z),
+        Some(Err(error)) => uprintln!(console, "Error: {}", error),
+        None => uprintln!(console, "No reading yet"),
+    };
+
+    Ok(())
+}
+
+// `leds <script>` plays a script right away, `leds load` takes one over several lines. The built-in
+// patterns are `roulette`, `chase`, `bounce`, `breathe`, `spin` and `sparkle`
+fn leds(console: &mut Console, args: &Args) -> Result<(), &'static str> {
+    let pattern = match args.get(0) {
+        None => {
+            let animation = &console.animation;
+            let direction = if animation.is_clockwise() {
+                "clockwise"
+            } else {
+                "counterclockwise"
+            };
+            uprintln!(
+                console,
+                "{}, {}% speed, {}",
+                pattern_name(animation.pattern()),
+                animation.speed(),
+                direction
+            );
+            return Ok(());
+        }
+        Some("load") => {
+            if console.capture.is_some() {
+                return Err("`leds load` needs a terminal, pass the script to `leds` instead");
+            }
+
+            uprintln!(
+                console,
+                "Enter the script, then `end` on a line of its own (Ctrl-C to cancel)"
+            );
+            console.editor = Some(Editor::default());
+            return Ok(());
+        }
+        Some("speed") => {
+            let speed: u16 = args.parse(1)?;
+            if !(1..=1_000).contains(&speed) {
+                return Err("speed out of range (1 to 1000%)");
+            }
+            console.animation.set_speed(speed);
+            return Ok(());
+        }
+        Some("reverse") => {
+            console.animation.reverse();
+            return Ok(());
+        }
+        Some("roulette") => roulette(),
+        Some("chase") => Pattern::Chase { step: 80, tail: 3 },
+        Some("bounce") => Pattern::Bounce { step: 100 },
+        Some("breathe") => Pattern::Breathe { period: 2_000 },
+        Some("spin") => Pattern::Spin { step: 60 },
+        Some("sparkle") => Pattern::Sparkle {
+            step: 150,
+            density: 48,
+        },
+        Some(_) => {
+            load_script(console, args.rest());
+            return Ok(());
+        }
+    };
+
+    console.animation.set_pattern(pattern);
+
+    Ok(())
+}
+
+fn pattern_name(pattern: &Pattern) -> &'static str {
+    match pattern {
+        Pattern::Chase { .. } => "chase",
+        Pattern::Bounce { .. } => "bounce",
+        Pattern::Breathe { .. } => "breathe",
+        Pattern::Spin { .. } => "spin",
+        Pattern::Sparkle { .. } => "sparkle",
+        Pattern::Keyframes(_) => "script",
+    }
+}
+
+// Parses `text` and plays it, or reports where it is wrong
+fn load_script(console: &mut Console, text: &str) {
+    match script::parse(text) {
+        Ok(keyframes) => {
+            let steps = keyframes.frames().len();
+            let duration = keyframes.duration();
+            console.animation.set_pattern(Pattern::Keyframes(keyframes));
+            uprintln!(console, "Playing {} steps, {} ms per loop", steps, duration);
+        }
+        Err(error) => {
+            uprintln!(console, "Error: {}", error);
+        }
+    }
+}
+
+// Runs the commands in the frames received so far, returns `false` once an empty frame has
+// ended framed mode
+fn poll_framed(
+    console: &mut Console,
+    shell: &Shell<Console, LINE_LENGTH>,
+    framed: &mut Framed,
+) -> bool {
+    loop {
+        // A byte lost to a receive error makes the CRC fail, the decoder then picks up again at
+        // the next delimiter
+        let byte = match console.serial.try_read_byte() {
+            Ok(Some(byte)) => byte,
+            Ok(None) => break,
+            Err(_) => continue,
+        };
+        if byte == 0 {
+            framed.frame_start = None;
+        } else if framed.frame_start.is_none() {
+            framed.frame_start = Some(console.timer.now());
+        }
+
+        // Bad frames are dropped, the sender notices the gap in the sequence numbers
+        let line = match framed.decoder.feed(byte) {
+            Some(Ok(frame)) if frame.payload.is_empty() => return false,
+            Some(Ok(frame)) => match Message::parse(frame.payload) {
+                Ok(Message::Command(line)) => line,
+                _ => continue,
+            },
+            Some(Err(_)) | None => continue,
+        };
+
+        console.capture = Some(String::new());
+        shell.execute(line, console);
+        let output = console.capture.take().unwrap_or_default();
+
+        let mut rest = output.as_str();
+        while !rest.is_empty() {
+            // Split on a character boundary
+            let mut end = rest.len().min(MAX_TEXT);
+            while !rest.is_char_boundary(end) {
+                end -= 1;
+            }
+
+            send_message(&mut console.serial, &mut framed.encoder, Message::Reply(&rest[..end]));
+            rest = &rest[end..];
         }
+        send_message(&mut console.serial, &mut framed.encoder, Message::Done);
+    }
+
+    let timeout = console.timer.ticks(FRAME_TIMEOUT);
+    if framed.frame_start.is_some_and(|start| start.elapsed() >= timeout) {
+        framed.decoder.discard_partial();
+        framed.frame_start = None;
+    }
+
+    true
+}
 
-        buffer.reverse();
+fn send_message<C: Crc16>(
+    serial: &mut SerialPort,
+    encoder: &mut FrameEncoder<C>,
+    message: Message,
+) {
+    let mut payload = [0; MAX_PAYLOAD];
+    let mut frame = [0; MAX_ENCODED_LEN];
 
-        for byte in &buffer {
-            uprint!(serial, "{}", *byte as char);
+    // Every message we send fits in a frame
+    if let Ok(len) = message.write(&mut payload) {
+        if let Ok(n) = encoder.encode(&payload[..len], &mut frame) {
+            serial.write_bytes(&frame[..n]);
         }
-        uprintln!(serial, "");
     }
 }
+
+#[exception]
+fn SysTick() {
+    aux11::monotimer::on_systick();
+}
+
+#[interrupt]
+fn DMA1_CH4() {
+    aux11::dma::on_transfer_complete();
+}
+
+#[interrupt]
+fn USART1_EXTI25() {
+    aux11::serial::on_interrupt();
+}
+
+#[interrupt]
+fn TIM1_UP_TIM16() {
+    aux11::pwm::on_update();
+}
@@ -0,0 +1,381 @@
+//! Line editing command shell
+//!
+//! Bytes read from the serial port are fed to `Shell::feed` one at a time. The shell echoes
+//! them back, handles backspace / DEL, Ctrl-C and the up / down arrow keys, and once a line is
+//! complete (CR, LF or CRLF) it looks the first word up in the command table and calls its
+//! handler with the remaining words as arguments.
+//!
+//! The maximum line length is the `N` const parameter of `Shell`, what happens to a line that
+//! doesn't fit is decided by its `OverflowPolicy`.
+
+use core::{fmt, mem, str, str::FromStr};
+
+use heapless::Vec;
+
+// Number of previous lines that can be recalled with the arrow keys
+const HISTORY_LENGTH: usize = 4;
+// Maximum number of arguments a command can take
+const MAX_ARGS: usize = 8;
+
+const PROMPT: &str = "> ";
+
+// ASCII control characters
+const CTRL_C: u8 = 0x03;
+const BACKSPACE: u8 = 0x08;
+const ESC: u8 = 0x1b;
+const DEL: u8 = 0x7f;
+
+pub type Line<const N: usize> = Vec<u8, N>;
+
+/// What to do with the bytes that don't fit in the line buffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Keep the first `N` bytes, drop the rest of the line and run what was kept
+    Truncate,
+    /// Drop the whole line, the command is not run
+    DiscardLine,
+    /// Run the first `N` bytes as if a line ending had been received, the remaining bytes start a
+    /// new line
+    FlushPartial,
+}
+
+/// Reported once per line that didn't fit in the line buffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineOverflow {
+    /// Size of the line buffer
+    pub max: usize,
+    /// Number of bytes that didn't make it into the line
+    pub dropped: usize,
+    pub policy: OverflowPolicy,
+}
+
+impl fmt::Display for LineOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line too long ({} bytes max", self.max)?;
+        if self.dropped != 0 {
+            write!(f, ", {} dropped", self.dropped)?;
+        }
+        f.write_str("), ")?;
+        f.write_str(match self.policy {
+            OverflowPolicy::Truncate => "truncated",
+            OverflowPolicy::DiscardLine => "discarded",
+            OverflowPolicy::FlushPartial => "flushed early",
+        })
+    }
+}
+
+/// The words that followed the command name
+pub struct Args<'a> {
+    args: Vec<&'a str, MAX_ARGS>,
+    rest: &'a str,
+}
+
+impl<'a> Args<'a> {
+    /// Everything after the command name, as it was typed
+    pub fn rest(&self) -> &'a str {
+        self.rest
+    }
+
+    pub fn len(&self) -> usize {
+        self.args.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&'a str> {
+        self.args.get(index).copied()
+    }
+
+    /// Parses the argument at `index`, e.g. `args.parse::<u32>(0)`
+    pub fn parse<T: FromStr>(&self, index: usize) -> Result<T, &'static str> {
+        self.get(index)
+            .ok_or("missing argument")?
+            .parse()
+            .map_err(|_| "invalid argument")
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &'a str> + '_ {
+        self.args.iter().copied()
+    }
+}
+
+/// Signature of a command handler, `C` is whatever the handlers need access to (it is also where
+/// the shell writes its output)
+pub type Handler<C> = fn(&mut C, &Args) -> Result<(), &'static str>;
+
+/// An entry of the command table
+pub struct Command<C> {
+    pub name: &'static str,
+    /// One line description printed by `help`
+    pub help: &'static str,
+    pub handler: Handler<C>,
+}
+
+// Progress through an ANSI escape sequence, the arrow keys send ESC [ A / ESC [ B
+enum Escape {
+    None,
+    Esc,
+    Csi,
+}
+
+pub struct Shell<C: 'static, const N: usize> {
+    commands: &'static [Command<C>],
+    policy: OverflowPolicy,
+    line: Line<N>,
+    // Bytes of the current line that didn't fit in `line`
+    dropped: usize,
+    history: Vec<Line<N>, HISTORY_LENGTH>,
+    // Index into `history` while walking it with the arrow keys, `None` when editing a new line
+    browsing: Option<usize>,
+    escape: Escape,
+    // The last byte was a CR, so a LF right after it is part of the same line ending
+    skip_lf: bool,
+}
+
+impl<C: fmt::Write, const N: usize> Shell<C, N> {
+    pub fn new(commands: &'static [Command<C>], policy: OverflowPolicy) -> Self {
+        Shell {
+            commands,
+            policy,
+            line: Vec::new(),
+            dropped: 0,
+            history: Vec::new(),
+            browsing: None,
+            escape: Escape::None,
+            skip_lf: false,
+        }
+    }
+
+    pub fn prompt(&self, out: &mut C) {
+        uprint!(out, "{}", PROMPT);
+    }
+
+    /// Drops the line being edited and starts a new one
+    pub fn cancel(&mut self, out: &mut C) {
+        self.line.clear();
+        self.dropped = 0;
+        self.browsing = None;
+        self.escape = Escape::None;
+        self.prompt(out);
+    }
+
+    /// Handles a byte received from the terminal
+    pub fn feed(&mut self, byte: u8, ctx: &mut C) {
+        match self.escape {
+            Escape::None => {}
+            Escape::Esc => {
+                self.escape = if byte == b'[' { Escape::Csi } else { Escape::None };
+                return;
+            }
+            Escape::Csi => {
+                self.escape = Escape::None;
+                match byte {
+                    b'A' => self.history_up(ctx),
+                    b'B' => self.history_down(ctx),
+                    // Left / right and friends are not supported
+                    _ => {}
+                }
+                return;
+            }
+        }
+
+        let skip_lf = mem::replace(&mut self.skip_lf, false);
+        match byte {
+            b'\r' => {
+                self.skip_lf = true;
+                self.submit(ctx);
+            }
+            b'\n' => {
+                if !skip_lf {
+                    self.submit(ctx);
+                }
+            }
+            BACKSPACE | DEL => self.erase(ctx),
+            CTRL_C => {
+                uprintln!(ctx, "^C");
+                self.cancel(ctx);
+            }
+            ESC => self.escape = Escape::Esc,
+            b' '..=b'~' | 0x80..=0xff => self.insert(byte, ctx),
+            // Ignore the remaining control characters
+            _ => {}
+        }
+    }
+
+    fn insert(&mut self, byte: u8, ctx: &mut C) {
+        if self.line.is_full() && self.policy == OverflowPolicy::FlushPartial {
+            // Run what we have so far, `byte` is the start of the next line
+            uprintln!(ctx, "");
+            self.report_overflow(0, ctx);
+            self.finish_line(ctx);
+        }
+
+        if self.line.push(byte).is_err() {
+            // Ring the terminal bell once, the error is reported when the line is submitted
+            if self.dropped == 0 {
+                uprint!(ctx, "\x07");
+            }
+            self.dropped += 1;
+            return;
+        }
+
+        // Multi-byte characters are only echoed once they are complete
+        let start = self.char_start();
+        if let Ok(s) = str::from_utf8(&self.line[start..]) {
+            uprint!(ctx, "{}", s);
+        }
+    }
+
+    fn erase(&mut self, ctx: &mut C) {
+        if self.line.is_empty() {
+            return;
+        }
+
+        let start = self.char_start();
+        self.line.truncate(start);
+        uprint!(ctx, "\x08 \x08");
+    }
+
+    // Index of the first byte of the last (possibly incomplete) UTF-8 character in the line
+    fn char_start(&self) -> usize {
+        self.line
+            .iter()
+            .rposition(|byte| byte & 0b1100_0000 != 0b1000_0000)
+            .unwrap_or(0)
+    }
+
+    fn submit(&mut self, ctx: &mut C) {
+        uprintln!(ctx, "");
+        self.finish_line(ctx);
+    }
+
+    // Runs the line and starts a new one
+    fn finish_line(&mut self, ctx: &mut C) {
+        let line = mem::take(&mut self.line);
+        let dropped = mem::replace(&mut self.dropped, 0);
+        self.browsing = None;
+
+        if dropped != 0 {
+            self.report_overflow(dropped, ctx);
+            if self.policy == OverflowPolicy::DiscardLine {
+                self.prompt(ctx);
+                return;
+            }
+        }
+
+        if !line.is_empty() {
+            if self.history.last() != Some(&line) {
+                if self.history.is_full() {
+                    self.history.rotate_left(1);
+                    self.history.pop();
+                }
+                self.history.push(line.clone()).ok();
+            }
+
+            self.run(&line, ctx);
+        }
+
+        self.prompt(ctx);
+    }
+
+    fn report_overflow(&self, dropped: usize, ctx: &mut C) {
+        let overflow = LineOverflow {
+            max: N,
+            dropped,
+            policy: self.policy,
+        };
+        uprintln!(ctx, "Error: {}", overflow);
+    }
+
+    fn run(&self, line: &[u8], ctx: &mut C) {
+        let line = match str::from_utf8(line) {
+            Ok(line) => line,
+            Err(error) => {
+                uprintln!(
+                    ctx,
+                    "Error: line is not valid UTF-8 (invalid byte at offset {})",
+                    error.valid_up_to()
+                );
+                return;
+            }
+        };
+
+        self.execute(line, ctx);
+    }
+
+    /// Runs a command line that didn't come from the line editor
+    pub fn execute(&self, line: &str, ctx: &mut C) {
+        let line = line.trim();
+        let mut words = line.split_whitespace();
+        let name = match words.next() {
+            Some(name) => name,
+            None => return,
+        };
+        let rest = line[name.len()..].trim_start();
+
+        let mut args = Vec::new();
+        for word in words {
+            if args.push(word).is_err() {
+                uprintln!(ctx, "Error: too many arguments (at most {})", MAX_ARGS);
+                return;
+            }
+        }
+
+        if name == "help" {
+            for command in self.commands {
+                uprintln!(ctx, "{:<10} {}", command.name, command.help);
+            }
+            return;
+        }
+
+        match self.commands.iter().find(|command| command.name == name) {
+            Some(command) => {
+                if let Err(error) = (command.handler)(ctx, &Args { args, rest }) {
+                    uprintln!(ctx, "Error: {}", error);
+                }
+            }
+            None => {
+                uprintln!(ctx, "Error: unknown command `{}`, try `help`", name);
+            }
+        }
+    }
+
+    fn history_up(&mut self, ctx: &mut C) {
+        if self.history.is_empty() {
+            return;
+        }
+
+        let index = match self.browsing {
+            None => self.history.len() - 1,
+            Some(index) => index.saturating_sub(1),
+        };
+        self.browsing = Some(index);
+        self.line = self.history[index].clone();
+        self.redraw(ctx);
+    }
+
+    fn history_down(&mut self, ctx: &mut C) {
+        match self.browsing {
+            None => return,
+            Some(index) if index + 1 < self.history.len() => {
+                self.browsing = Some(index + 1);
+                self.line = self.history[index + 1].clone();
+            }
+            Some(_) => {
+                self.browsing = None;
+                self.line.clear();
+            }
+        }
+        self.redraw(ctx);
+    }
+
+    fn redraw(&self, ctx: &mut C) {
+        // Go back to the start of the line and clear it
+        uprint!(ctx, "\r\x1b[K{}", PROMPT);
+        if let Ok(s) = str::from_utf8(&self.line) {
+            uprint!(ctx, "{}", s);
+        }
+    }
+}
@@ -0,0 +1,311 @@
+//! Minimal `async` executor for the board
+//!
+//! `run` polls a fixed set of tasks, `block_on` a single future. Between polls nothing is done
+//! until a task is woken, and while no task is ready the `idle` function passed in is called,
+//! which on the board sleeps until the next interrupt:
+//!
+//! ``` ignore
+//! fn idle() {
+//!     // An interrupt between the check and WFI still ends WFI, it is only handled afterwards
+//!     cortex_m::interrupt::free(|_| {
+//!         if !executor::is_ready() {
+//!             cortex_m::asm::wfi();
+//!         }
+//!     });
+//! }
+//!
+//! executor::run(&mut [pin!(blink()), pin!(echo(serial))], idle);
+//! ```
+//!
+//! Interrupt handlers wake tasks through a `Signal`. Only one executor can be running at a time,
+//! `run` and `block_on` panic when called from inside a task.
+
+#![cfg_attr(not(test), no_std)]
+
+mod signal;
+
+pub use signal::Signal;
+
+use core::{
+    future::{poll_fn, Future},
+    pin::{pin, Pin},
+    ptr,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+
+/// Maximum number of tasks `run` can handle
+pub const MAX_TASKS: usize = 32;
+
+/// A task, usually made with `pin!(some_async_fn())`
+pub type Task<'a> = Pin<&'a mut dyn Future<Output = ()>>;
+
+// One bit per task that has been woken and has to be polled again
+static READY: AtomicU32 = AtomicU32::new(0);
+// Set while `run` is running
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+// A waker is just the index of its task
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+
+unsafe fn clone(data: *const ()) -> RawWaker {
+    RawWaker::new(data, &VTABLE)
+}
+
+unsafe fn wake(data: *const ()) {
+    wake_task(data as usize);
+}
+
+unsafe fn drop(_: *const ()) {}
+
+fn waker(task: usize) -> Waker {
+    // NOTE(unsafe) the vtable functions don't dereference the data pointer
+    unsafe { Waker::from_raw(RawWaker::new(task as *const (), &VTABLE)) }
+}
+
+// The task `waker` wakes, `None` if it isn't one of ours
+fn task_of(waker: &Waker) -> Option<usize> {
+    ptr::eq(waker.vtable(), &VTABLE).then(|| waker.data() as usize)
+}
+
+fn wake_task(task: usize) {
+    READY.fetch_or(1 << task, Ordering::Relaxed);
+}
+
+/// Returns `true` if a task has been woken and is waiting to be polled
+pub fn is_ready() -> bool {
+    READY.load(Ordering::Relaxed) != 0
+}
+
+/// Polls `tasks` until all of them have completed, calls `idle` while none of them is ready
+///
+/// # Panics
+///
+/// If there are more than `MAX_TASKS` tasks, or if called from inside a task
+pub fn run(tasks: &mut [Task<'_>], idle: fn()) {
+    assert!(tasks.len() <= MAX_TASKS, "too many tasks");
+    assert!(
+        !RUNNING.swap(true, Ordering::Relaxed),
+        "the executor is already running"
+    );
+    let _running = Running;
+
+    let all = match tasks.len() {
+        MAX_TASKS => u32::MAX,
+        len => (1 << len) - 1,
+    };
+    let mut done = 0;
+
+    // Every task gets polled once to get it going
+    READY.store(all, Ordering::Relaxed);
+
+    while done != all {
+        let ready = READY.swap(0, Ordering::Relaxed) & !done;
+        if ready == 0 {
+            idle();
+            continue;
+        }
+
+        for (index, task) in tasks.iter_mut().enumerate() {
+            if ready & 1 << index == 0 {
+                continue;
+            }
+
+            let waker = waker(index);
+            if task
+                .as_mut()
+                .poll(&mut Context::from_waker(&waker))
+                .is_ready()
+            {
+                done |= 1 << index;
+            }
+        }
+    }
+}
+
+// Clears `RUNNING` when `run` returns, or when a task panics
+struct Running;
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Polls `future` until it completes, calls `idle` while it is not ready
+///
+/// # Panics
+///
+/// If called from inside a task
+pub fn block_on<F: Future>(future: F, idle: fn()) -> F::Output {
+    let mut output = None;
+    {
+        let task = pin!(async {
+            output = Some(future.await);
+        });
+        run(&mut [task], idle);
+    }
+
+    // `run` only returns once the task is done
+    output.unwrap()
+}
+
+/// Lets the other tasks run before continuing
+pub async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        sync::{Mutex, MutexGuard},
+        vec::Vec,
+    };
+
+    // The executor state is global, the tests can't run it at the same time
+    fn lock() -> MutexGuard<'static, ()> {
+        static LOCK: Mutex<()> = Mutex::new(());
+        LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn never_idle() {
+        panic!("idle with a task ready");
+    }
+
+    #[test]
+    fn block_on_returns_the_output() {
+        let _lock = lock();
+
+        assert_eq!(block_on(async { 42 }, never_idle), 42);
+        assert_eq!(
+            block_on(
+                async {
+                    yield_now().await;
+                    "done"
+                },
+                never_idle
+            ),
+            "done"
+        );
+    }
+
+    #[test]
+    fn yield_now_interleaves_tasks() {
+        let _lock = lock();
+        let log = Mutex::new(Vec::new());
+
+        let task = |name| {
+            let log = &log;
+            async move {
+                for step in 0..3 {
+                    log.lock().unwrap().push((name, step));
+                    yield_now().await;
+                }
+            }
+        };
+        run(&mut [pin!(task("a")), pin!(task("b"))], never_idle);
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            [("a", 0), ("b", 0), ("a", 1), ("b", 1), ("a", 2), ("b", 2)]
+        );
+    }
+
+    // What the "interrupt handler" in `idle` counts up, and the signal it wakes the tasks with
+    static EVENTS: AtomicU32 = AtomicU32::new(0);
+    static SIGNAL: Signal = Signal::new();
+
+    fn interrupt() {
+        EVENTS.fetch_add(1, Ordering::Relaxed);
+        SIGNAL.wake();
+    }
+
+    #[test]
+    fn signal_wakes_waiting_tasks_in_order() {
+        let _lock = lock();
+        EVENTS.store(0, Ordering::Relaxed);
+        let log = Mutex::new(Vec::new());
+
+        // Task `name` finishes after `events` interrupts, counting how often it was polled
+        let task = |name, events| {
+            let log = &log;
+            async move {
+                let mut polls = 0;
+                SIGNAL
+                    .wait(|| {
+                        polls += 1;
+                        (EVENTS.load(Ordering::Relaxed) >= events).then_some(())
+                    })
+                    .await;
+                log.lock().unwrap().push((name, polls));
+            }
+        };
+        run(
+            &mut [
+                pin!(task("late", 2)),
+                pin!(task("early", 1)),
+                pin!(task("now", 0)),
+            ],
+            interrupt,
+        );
+
+        // Polled once at the start, then once per wake
+        assert_eq!(
+            *log.lock().unwrap(),
+            [("now", 1), ("early", 2), ("late", 3)]
+        );
+    }
+
+    #[test]
+    fn signal_only_wakes_registered_tasks() {
+        let _lock = lock();
+        EVENTS.store(0, Ordering::Relaxed);
+        static OTHER: Signal = Signal::new();
+        let polls = AtomicU32::new(0);
+
+        let waiting = async {
+            SIGNAL
+                .wait(|| (EVENTS.load(Ordering::Relaxed) >= 3).then_some(()))
+                .await;
+            OTHER.wake();
+        };
+        // Waits for `OTHER`, the three wakes of `SIGNAL` don't poll it
+        let other = OTHER.wait(|| {
+            let polls = polls.fetch_add(1, Ordering::Relaxed) + 1;
+            (EVENTS.load(Ordering::Relaxed) >= 3).then_some(polls)
+        });
+        let other = async {
+            assert_eq!(other.await, 2);
+        };
+
+        run(&mut [pin!(waiting), pin!(other)], interrupt);
+    }
+
+    #[test]
+    #[should_panic(expected = "the executor's own wakers")]
+    fn signal_rejects_other_wakers() {
+        let _lock = lock();
+
+        SIGNAL.register(Waker::noop());
+    }
+
+    #[test]
+    #[should_panic(expected = "already running")]
+    fn nested_block_on() {
+        let _lock = lock();
+
+        block_on(async { block_on(async {}, never_idle) }, never_idle);
+    }
+}
@@ -0,0 +1,71 @@
+//! Waking tasks from interrupt handlers
+
+use core::{
+    future::{poll_fn, Future},
+    sync::atomic::{AtomicU32, Ordering},
+    task::{Poll, Waker},
+};
+
+use crate::{task_of, READY};
+
+/// Something an interrupt handler can wake tasks up for, e.g. "a byte was received"
+///
+/// Tasks wait with `Signal::wait`, the interrupt handler calls `Signal::wake`. Waking doesn't
+/// carry any data, the waiting task checks its condition again and goes back to sleep if it
+/// still doesn't hold.
+///
+/// This only works with the wakers of this crate's executor. A future polled by a combinator that
+/// passes its own waker down (e.g. a `join` or a `select`) would never be woken up, so `register`
+/// panics on any other waker.
+pub struct Signal {
+    // One bit per task waiting for the signal
+    waiting: AtomicU32,
+}
+
+impl Signal {
+    pub const fn new() -> Self {
+        Signal {
+            waiting: AtomicU32::new(0),
+        }
+    }
+
+    /// Wakes every task waiting for the signal, can be called from an interrupt handler
+    pub fn wake(&self) {
+        let waiting = self.waiting.swap(0, Ordering::Relaxed);
+        READY.fetch_or(waiting, Ordering::Relaxed);
+    }
+
+    /// Wakes the task of `waker` the next time `wake` is called
+    ///
+    /// Must be called from `Future::poll`, with the waker of its `Context`, before checking the
+    /// condition the task waits for, otherwise a `wake` in between gets lost
+    ///
+    /// # Panics
+    ///
+    /// If `waker` doesn't come from `run` or `block_on`
+    pub fn register(&self, waker: &Waker) {
+        let task = task_of(waker).expect("`Signal` only works with the executor's own wakers");
+        self.waiting.fetch_or(1 << task, Ordering::Relaxed);
+    }
+
+    /// Waits until `condition` returns something, it is checked once now and then every time
+    /// the signal is woken
+    pub fn wait<'a, T>(
+        &'a self,
+        mut condition: impl FnMut() -> Option<T> + 'a,
+    ) -> impl Future<Output = T> + 'a {
+        poll_fn(move |cx| {
+            self.register(cx.waker());
+            match condition() {
+                Some(value) => Poll::Ready(value),
+                None => Poll::Pending,
+            }
+        })
+    }
+}
+
+impl Default for Signal {
+    fn default() -> Self {
+        Signal::new()
+    }
+}
@@ -0,0 +1,120 @@
+//! Framed connection to the board
+
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    time::{Duration, Instant},
+};
+
+use protocol::{
+    framing::{FrameDecoder, FrameEncoder, SoftwareCrc, MAX_ENCODED_LEN, MAX_PAYLOAD},
+    message::{Message, MessageError},
+};
+use serialport::SerialPort;
+
+// How long a single read on the port may block
+const READ_TIMEOUT: Duration = Duration::from_millis(50);
+
+pub struct Link {
+    port: Box<dyn SerialPort>,
+    encoder: FrameEncoder<SoftwareCrc>,
+    decoder: FrameDecoder<SoftwareCrc>,
+    // Bytes read from the port but not fed to the decoder yet
+    pending: VecDeque<u8>,
+    // Payload of the last frame received
+    payload: Vec<u8>,
+}
+
+impl Link {
+    /// Opens a serial device (or PTY) at the given baud rate
+    pub fn open(path: &str, baud_rate: u32) -> serialport::Result<Self> {
+        let port = serialport::new(path, baud_rate)
+            .timeout(READ_TIMEOUT)
+            .open()?;
+
+        Ok(Link {
+            port,
+            encoder: FrameEncoder::new(SoftwareCrc),
+            decoder: FrameDecoder::new(SoftwareCrc),
+            pending: VecDeque::new(),
+            payload: Vec::new(),
+        })
+    }
+
+    pub fn send(&mut self, message: Message) -> io::Result<()> {
+        let mut payload = [0; MAX_PAYLOAD];
+        let mut frame = [0; MAX_ENCODED_LEN];
+
+        let len = message.write(&mut payload).map_err(invalid_input)?;
+        let n = self
+            .encoder
+            .encode(&payload[..len], &mut frame)
+            .map_err(invalid_input)?;
+
+        self.port.write_all(&frame[..n])?;
+        self.port.flush()
+    }
+
+    /// Waits for the next valid message, `None` if `timeout` expires first (`timeout = None`
+    /// waits forever)
+    ///
+    /// Frames that fail to decode and gaps in the sequence numbers are reported on stderr, the
+    /// link keeps going
+    pub fn recv(&mut self, timeout: Option<Duration>) -> io::Result<Option<Message<'_>>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            if self.next_frame()? {
+                break;
+            }
+
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(None);
+            }
+        }
+
+        Message::parse(&self.payload)
+            .map(Some)
+            .map_err(|error: MessageError| {
+                io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+            })
+    }
+
+    // Feeds bytes to the decoder until a frame comes out or the port has nothing more, returns
+    // whether `payload` now holds a new frame
+    fn next_frame(&mut self) -> io::Result<bool> {
+        if self.pending.is_empty() {
+            let mut buffer = [0; 256];
+            match self.port.read(&mut buffer) {
+                Ok(n) => self.pending.extend(&buffer[..n]),
+                Err(error) if error.kind() == io::ErrorKind::TimedOut => return Ok(false),
+                Err(error) => return Err(error),
+            }
+        }
+
+        while let Some(byte) = self.pending.pop_front() {
+            match self.decoder.feed(byte) {
+                Some(Ok(frame)) => {
+                    if frame.missed != 0 {
+                        eprintln!(
+                            "warning: {} frame(s) lost before #{}",
+                            frame.missed, frame.seq
+                        );
+                    }
+
+                    self.payload.clear();
+                    self.payload.extend_from_slice(frame.payload);
+                    return Ok(true);
+                }
+                Some(Err(error)) => eprintln!("warning: dropped a frame: {}", error),
+                None => {}
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+fn invalid_input(error: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, error.to_string())
+}
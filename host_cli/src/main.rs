@@ -0,0 +1,151 @@
+//! Host side companion of the echo_server firmware
+//!
+//! Talks to the board (or a PTY) with the framed protocol of the `protocol` crate. The board
+//! has to be in framed mode: enter `framed` in its shell before starting this.
+
+use std::{
+    env,
+    error::Error,
+    fs::File,
+    io::{self, BufRead, BufWriter, Write},
+    process,
+    time::Duration,
+};
+
+use protocol::message::{Message, MAX_TEXT};
+
+mod link;
+
+use link::Link;
+
+const USAGE: &str = "\
+Usage: host_cli <device> [--baud <rate>] <command>
+
+Commands:
+    shell             Send the lines read from stdin as commands and print the replies
+    send <line>       Send one command and print its reply
+    log <file.csv>    Turn the magnetometer stream on and write every sample received to a CSV
+                      file, until interrupted
+";
+
+// Same as the firmware
+const DEFAULT_BAUD_RATE: u32 = 9600;
+// How long the board gets to start answering a command
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn main() {
+    if let Err(error) = run(env::args().skip(1).collect()) {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let mut args = args.into_iter();
+    let mut baud_rate = DEFAULT_BAUD_RATE;
+
+    let device = match args.next() {
+        Some(device) if !device.starts_with('-') => device,
+        _ => return Err(USAGE.into()),
+    };
+
+    let mut command = args.next().ok_or(USAGE)?;
+    if command == "--baud" {
+        baud_rate = args.next().ok_or(USAGE)?.parse()?;
+        command = args.next().ok_or(USAGE)?;
+    }
+
+    let mut link = Link::open(&device, baud_rate)?;
+
+    match command.as_str() {
+        "shell" => shell(&mut link),
+        "send" => {
+            let line = args.collect::<Vec<_>>().join(" ");
+            send(&mut link, &line)
+        }
+        "log" => {
+            let path = args.next().ok_or(USAGE)?;
+            log(&mut link, &path)
+        }
+        _ => Err(USAGE.into()),
+    }
+}
+
+fn shell(link: &mut Link) -> Result<(), Box<dyn Error>> {
+    let stdin = io::stdin();
+
+    prompt()?;
+    for line in stdin.lock().lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            if let Err(error) = send(link, &line) {
+                eprintln!("error: {}", error);
+            }
+        }
+        prompt()?;
+    }
+
+    Ok(())
+}
+
+fn prompt() -> io::Result<()> {
+    print!("> ");
+    io::stdout().flush()
+}
+
+// Sends a command and prints the replies until the board says it's done
+fn send(link: &mut Link, line: &str) -> Result<(), Box<dyn Error>> {
+    if line.len() > MAX_TEXT {
+        return Err(format!("command longer than {} bytes", MAX_TEXT).into());
+    }
+
+    link.send(Message::Command(line))?;
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    loop {
+        match link.recv(Some(REPLY_TIMEOUT))? {
+            Some(Message::Reply(text)) => stdout.write_all(text.as_bytes())?,
+            Some(Message::Done) => break,
+            // Samples streamed in the meantime are not what we're waiting for
+            Some(_) => {}
+            None => return Err("no reply from the board".into()),
+        }
+    }
+    stdout.flush()?;
+
+    Ok(())
+}
+
+fn log(link: &mut Link, path: &str) -> Result<(), Box<dyn Error>> {
+    let mut csv = BufWriter::new(File::create(path)?);
+    writeln!(csv, "timestamp_us,x,y,z")?;
+
+    send(link, "stream on")?;
+
+    let mut count = 0u64;
+    loop {
+        let message = match link.recv(None) {
+            Ok(message) => message,
+            // A frame that made it through the CRC but isn't a message we know, skip it
+            Err(error) if error.kind() == io::ErrorKind::InvalidData => {
+                eprintln!("\nwarning: {}", error);
+                continue;
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        if let Some(Message::Sample(sample)) = message {
+            writeln!(
+                csv,
+                "{},{},{},{}",
+                sample.timestamp_us, sample.x, sample.y, sample.z
+            )?;
+            // Flush every line so nothing is lost when the logger is interrupted
+            csv.flush()?;
+
+            count += 1;
+            eprint!("\r{} samples", count);
+        }
+    }
+}
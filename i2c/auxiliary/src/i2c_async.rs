@@ -0,0 +1,151 @@
+//! Interrupt driven I2C1 transactions for `async` code
+//!
+//! These do the same as the busy waiting code in `i2c`'s `main.rs`, but sleep until the I2C1
+//! event interrupt instead of spinning on the ISR flags. Call `enable` once, and forward the
+//! interrupt to `on_interrupt`:
+//!
+//! ``` ignore
+//! #[interrupt]
+//! fn I2C1_EV_EXTI23() {
+//!     aux14::i2c_async::on_interrupt();
+//! }
+//!
+//! let mut whoami = [0];
+//! i2c_async::read_registers(i2c1, MAGNETOMETER, WHO_AM_I_M, &mut whoami).await?;
+//! ```
+//!
+//! A transaction that the device doesn't acknowledge ends with `Err(Nack)`, the I2C1 has sent
+//! STOP by then and is ready for the next one.
+
+use core::fmt;
+
+use cortex_m::peripheral::NVIC;
+use executor::Signal;
+use stm32f3_discovery::stm32f3xx_hal::stm32::{
+    i2c1::{isr, RegisterBlock},
+    Interrupt, I2C1,
+};
+
+// Woken by the interrupt handler whenever one of the flags we wait for is set
+static EVENT: Signal = Signal::new();
+
+/// The device didn't acknowledge its address or a byte
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Nack;
+
+impl fmt::Display for Nack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("no answer from the device (NACK)")
+    }
+}
+
+/// Unmasks the I2C1 event interrupt
+pub fn enable() {
+    unsafe { NVIC::unmask(Interrupt::I2C1_EV_EXTI23) };
+}
+
+/// Must be called from the I2C1_EV_EXTI23 interrupt handler
+pub fn on_interrupt() {
+    // NOTE(unsafe) the task waiting for the event doesn't touch CR1 until it is woken
+    let i2c1 = unsafe { &*I2C1::ptr() };
+
+    // The flags stay set until the task handles them, mask them or we'd be back here right away
+    i2c1.cr1.modify(|_, w| {
+        w.txie().clear_bit();
+        w.rxie().clear_bit();
+        w.tcie().clear_bit();
+        w.nackie().clear_bit()
+    });
+
+    EVENT.wake();
+}
+
+// Waits until `flag` is set, with the interrupts for it enabled in the meantime. On a NACK the
+// I2C1 sends STOP by itself (AUTOEND or not), so there is nothing left to clean up besides the flag
+async fn wait_for(i2c1: &RegisterBlock, flag: fn(&isr::R) -> bool) -> Result<(), Nack> {
+    EVENT
+        .wait(|| {
+            let isr = i2c1.isr.read();
+
+            if isr.nackf().bit_is_set() {
+                i2c1.icr.write(|w| w.nackcf().set_bit());
+                return Some(Err(Nack));
+            }
+
+            if flag(&isr) {
+                return Some(Ok(()));
+            }
+
+            // TXIE, RXIE, TCIE, NACKIE: Interrupt when TXIS, RXNE, TC or NACKF are set
+            i2c1.cr1.modify(|_, w| {
+                w.txie().set_bit();
+                w.rxie().set_bit();
+                w.tcie().set_bit();
+                w.nackie().set_bit()
+            });
+            None
+        })
+        .await
+}
+
+/// Reads `buffer.len()` consecutive registers, starting at `register`, of the device at
+/// `address` (up to 255)
+pub async fn read_registers(
+    i2c1: &RegisterBlock,
+    address: u16,
+    register: u8,
+    buffer: &mut [u8],
+) -> Result<(), Nack> {
+    // Broadcast START and the address with the R/W bit set to Write
+    i2c1.cr2.write(|w| {
+        w.start().set_bit();
+        w.sadd().bits(address);
+        w.rd_wrn().clear_bit();
+        w.nbytes().bits(1);
+        w.autoend().clear_bit()
+    });
+
+    // Send the address of the first register we want to read
+    wait_for(i2c1, |isr| isr.txis().bit_is_set()).await?;
+    i2c1.txdr.write(|w| w.txdata().bits(register));
+    wait_for(i2c1, |isr| isr.tc().bit_is_set()).await?;
+
+    // Broadcast RESTART and the address with the R/W bit set to Read, STOP is automatic
+    i2c1.cr2.modify(|_, w| {
+        w.start().set_bit();
+        w.nbytes().bits(buffer.len() as u8);
+        w.rd_wrn().set_bit();
+        w.autoend().set_bit()
+    });
+
+    for byte in buffer {
+        wait_for(i2c1, |isr| isr.rxne().bit_is_set()).await?;
+        *byte = i2c1.rxdr.read().rxdata().bits();
+    }
+
+    Ok(())
+}
+
+/// Writes `value` to `register` of the device at `address`
+pub async fn write_register(
+    i2c1: &RegisterBlock,
+    address: u16,
+    register: u8,
+    value: u8,
+) -> Result<(), Nack> {
+    // Broadcast START and the address with the R/W bit set to Write, STOP is automatic
+    i2c1.cr2.write(|w| {
+        w.start().set_bit();
+        w.sadd().bits(address);
+        w.rd_wrn().clear_bit();
+        w.nbytes().bits(2);
+        w.autoend().set_bit()
+    });
+
+    for byte in [register, value] {
+        wait_for(i2c1, |isr| isr.txis().bit_is_set()).await?;
+        i2c1.txdr.write(|w| w.txdata().bits(byte));
+    }
+
+    Ok(())
+}
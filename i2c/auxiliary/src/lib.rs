@@ -6,8 +6,16 @@
 extern crate panic_itm; // panic handler
 
 pub use cortex_m::{asm::bkpt, iprint, iprintln};
-pub use cortex_m_rt::entry;
-pub use stm32f3_discovery::stm32f3xx_hal::{delay::Delay, prelude, stm32::i2c1};
+pub use aux_common::{delay::Delay, idle, profile};
+pub use clocks::ClockProfile;
+pub use cortex_m_rt::{entry, exception};
+pub use stm32f3_discovery::stm32f3xx_hal::{
+    prelude,
+    rcc::Clocks,
+    stm32::{i2c1, interrupt},
+};
+
+pub mod i2c_async;
 
 use cortex_m::peripheral::ITM;
 use stm32f3_discovery::{
@@ -15,18 +23,23 @@ use stm32f3_discovery::{
     stm32f3xx_hal::{
         i2c::I2c,
         prelude::*,
+        rcc::CFGR,
         stm32::{self, I2C1},
     },
 };
+use clocks::HSE_FREQUENCY;
 
-pub fn init() -> (&'static i2c1::RegisterBlock, Delay, ITM) {
-    let cp = cortex_m::Peripherals::take().unwrap();
+pub fn init(profile: ClockProfile) -> (&'static i2c1::RegisterBlock, Delay, ITM, Clocks) {
+    let mut cp = cortex_m::Peripherals::take().unwrap();
     let dp = stm32::Peripherals::take().unwrap();
 
     let mut flash = dp.FLASH.constrain();
     let mut rcc = dp.RCC.constrain();
 
-    let clocks = rcc.cfgr.freeze(&mut flash.acr);
+    let clocks = configure_clocks(rcc.cfgr, &profile).freeze(&mut flash.acr);
+    profile
+        .check(clocks.sysclk().0, clocks.hclk().0, clocks.pclk1().0, clocks.pclk2().0)
+        .unwrap_or_else(|mismatch| panic!("{}", mismatch));
 
     let mut gpiob = dp.GPIOB.split(&mut rcc.ahb);
     let scl = gpiob.pb6.into_af4(&mut gpiob.moder, &mut gpiob.afrl);
@@ -36,7 +49,26 @@ pub fn init() -> (&'static i2c1::RegisterBlock, Delay, ITM) {
 
     Lsm303dlhc::new(i2c).unwrap();
 
-    let delay = Delay::new(cp.SYST, clocks);
+    // Sleeps until the SysTick exception, see `delay`
+    let delay = Delay::new(cp.SYST, clocks.hclk().0);
+
+    // Used by `profile!`
+    aux_common::cycles::start(cp.DWT);
+
+    unsafe { (&mut *(I2C1::ptr() as *mut _), delay, cp.ITM, clocks) }
+}
+
+// Hands `profile` to the HAL, which picks the PLL multiplier, the prescalers and the flash wait
+// states
+fn configure_clocks(cfgr: CFGR, profile: &ClockProfile) -> CFGR {
+    let cfgr = if profile.uses_hse() {
+        cfgr.use_hse(HSE_FREQUENCY.hz()).bypass_hse()
+    } else {
+        cfgr
+    };
 
-    unsafe { (&mut *(I2C1::ptr() as *mut _), delay, cp.ITM) }
-}
\ No newline at end of file
+    cfgr.sysclk(profile.sysclk.hz())
+        .hclk(profile.hclk.hz())
+        .pclk1(profile.pclk1.hz())
+        .pclk2(profile.pclk2.hz())
+}
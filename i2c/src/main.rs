@@ -4,7 +4,9 @@
 
 use aux14::i2c1::RegisterBlock;
 #[allow(unused_imports)]
-use aux14::{entry, iprint, iprintln, prelude::*};
+use aux14::{
+    entry, exception, i2c_async, interrupt, iprint, iprintln, prelude::*, profile, ClockProfile,
+};
 
 // Slave address
 const MAGNETOMETER: u16 = 0b0011_1100;
@@ -15,9 +17,13 @@ const WHO_AM_I_M: u8 = 0x4F;
 const CFG_REG_A_M: u8 = 0x60;
 const OUTX_L_REG_M: u8 = 0x068;
 
+// Print the profiling table every this many readings
+const REPORT_EVERY: u32 = 10;
+
 #[entry]
 fn main() -> ! {
-    let (i2c1, mut delay, mut itm) = aux14::init();
+    let (i2c1, mut delay, mut itm, clocks) = aux14::init(ClockProfile::HSI_8MHZ);
+    i2c_async::enable();
 
     let cfg_reg_a_m_byte: u8 = set_mode_continuous(i2c1);
     // Expected output:  0x60 - 0b00000000
@@ -28,11 +34,14 @@ fn main() -> ! {
         cfg_reg_a_m_byte
     );
 
-    let whoami: u8 = who_am_i(i2c1);
+    let whoami: u8 = profile!("who_am_i", who_am_i(i2c1));
     // Expected output:  0x4F - 0b01000000
     iprintln!(&mut itm.stim[0], "0x{:02X} - 0b{:08b}", WHO_AM_I_M, whoami);
 
+    let mut readings = 0;
     loop {
+        let burst_read = profile!("burst read");
+
         // ask for an array of 6 register values starting at OUTX_L_REG_M (0x68)
         {
             // Broadcast START
@@ -70,6 +79,7 @@ fn main() -> ! {
             *byte = i2c1.rxdr.read().rxdata().bits();
         }
         // Broadcast STOP (automatic because of `AUTOEND = 1`)
+        drop(burst_read);
 
         iprintln!(&mut itm.stim[0], "{:?}", buffer);
 
@@ -86,6 +96,24 @@ fn main() -> ! {
 
         iprintln!(&mut itm.stim[0], "{:?}", (x, y, z));
 
+        // The same read with `i2c_async`, which sleeps until the I2C1 interrupt instead of
+        // spinning on the flags. It isn't profiled: the cycle counter stands still while the core
+        // sleeps, so it would look faster than it is
+        let mut async_buffer = [0u8; 6];
+        let result = executor::block_on(
+            i2c_async::read_registers(i2c1, MAGNETOMETER, OUTX_L_REG_M, &mut async_buffer),
+            aux14::idle,
+        );
+        match result {
+            Ok(()) => iprintln!(&mut itm.stim[0], "{:?}", async_buffer),
+            Err(nack) => iprintln!(&mut itm.stim[0], "Error: {}", nack),
+        }
+
+        readings += 1;
+        if readings % REPORT_EVERY == 0 {
+            profile::report_itm(&mut itm.stim[0], clocks.hclk().0);
+        }
+
         delay.delay_ms(1_000_u16);
     }
 }
@@ -179,3 +207,12 @@ fn set_mode_continuous(i2c1: &RegisterBlock) -> (u8) {
 
     cfg_reg_a_m_byte
 }
+
+// `delay` only needs SysTick to wake the core up from WFI, there is nothing to do here
+#[exception]
+fn SysTick() {}
+
+#[interrupt]
+fn I2C1_EV_EXTI23() {
+    i2c_async::on_interrupt();
+}
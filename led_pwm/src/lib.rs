@@ -0,0 +1,299 @@
+//! Brightness control for the eight user LEDs
+//!
+//! PE9, PE11, PE13 and PE14 (N, E, S and SW) are the TIM1 CH1-CH4 pins, those LEDs are driven by
+//! hardware PWM. The other four have no timer channel of their own (PE8, PE10 and PE12 only
+//! carry the complementary outputs of CH1-CH3), they are modulated in software from the TIM16
+//! update interrupt, which the application has to forward to `on_update`:
+//!
+//! ``` ignore
+//! let (mut delay, mut leds) = aux5::init_pwm(ClockProfile::HSI_8MHZ);
+//! leds.set_brightness(Direction::N, 128);
+//!
+//! #[interrupt]
+//! fn TIM1_UP_TIM16() {
+//!     aux5::pwm::on_update();
+//! }
+//! ```
+//!
+//! Brightness goes through a gamma 2.2 table, so a fade with evenly spaced steps looks even too.
+//!
+//! The TIM16 interrupt only runs while one of the software LEDs is dimmed, LEDs that are fully on
+//! or off are set once and don't wake the core up.
+//!
+//! The auxiliary crates on HAL 0.7 share this, `aux5` and `aux11` re-export it as `pwm`.
+
+#![no_std]
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use clocks::timer_clock;
+use compass_leds::Frame;
+use cortex_m::{interrupt, peripheral::NVIC};
+use stm32f3_discovery::{
+    stm32f3xx_hal::{
+        gpio::{gpioe, Output, PushPull},
+        pac::{self, Interrupt, GPIOE, TIM1, TIM16},
+        rcc::Clocks,
+    },
+    switch_hal::{ActiveHigh, Switch},
+};
+
+/// The eight user LEDs, clockwise starting with the north one, see `compass_leds::Direction`
+pub type LedArray = [Switch<gpioe::PEx<Output<PushPull>>, ActiveHigh>; 8];
+
+/// Nominal frequency of the hardware PWM. The prescaler is rounded to the nearest whole divider
+/// of the timer clock, so the actual rate is a bit off: 7843 Hz both at 8 MHz and at 48 MHz
+pub const PWM_FREQUENCY: u32 = 8_000;
+
+/// Highest rate at which the software PWM steps. A software LED at the lowest duty cycle
+/// (1 / 255) then still blinks at about 125 Hz, which doesn't flicker
+pub const SOFTWARE_PWM_FREQUENCY: u32 = 32_000;
+
+/// Fewest core clock cycles between two software PWM steps. Each step is an interrupt, this
+/// keeps them to about a tenth of the CPU time so that e.g. the USART receive interrupt still
+/// gets its turn. Below 32 MHz the software PWM steps slower than `SOFTWARE_PWM_FREQUENCY`, see
+/// `software_pwm_frequency`
+pub const MIN_STEP_CYCLES: u32 = 1_000;
+
+// Counter period in ticks, CCR = 255 (full brightness) is then above ARR and the output never
+// goes low
+const PERIOD: u32 = 255;
+
+/// Perceived brightness to duty cycle
+pub const GAMMA: [u8; 256] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2,
+    3, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 6, 6, 6,
+    6, 7, 7, 7, 8, 8, 8, 9, 9, 9, 10, 10, 11, 11, 11, 12,
+    12, 13, 13, 13, 14, 14, 15, 15, 16, 16, 17, 17, 18, 18, 19, 19,
+    20, 20, 21, 22, 22, 23, 23, 24, 25, 25, 26, 26, 27, 28, 28, 29,
+    30, 30, 31, 32, 33, 33, 34, 35, 35, 36, 37, 38, 39, 39, 40, 41,
+    42, 43, 43, 44, 45, 46, 47, 48, 49, 49, 50, 51, 52, 53, 54, 55,
+    56, 57, 58, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69, 70, 71,
+    73, 74, 75, 76, 77, 78, 79, 81, 82, 83, 84, 85, 87, 88, 89, 90,
+    91, 93, 94, 95, 97, 98, 99, 100, 102, 103, 105, 106, 107, 109, 110, 111,
+    113, 114, 116, 117, 119, 120, 121, 123, 124, 126, 127, 129, 130, 132, 133, 135,
+    137, 138, 140, 141, 143, 145, 146, 148, 149, 151, 153, 154, 156, 158, 159, 161,
+    163, 165, 166, 168, 170, 172, 173, 175, 177, 179, 181, 182, 184, 186, 188, 190,
+    192, 194, 196, 197, 199, 201, 203, 205, 207, 209, 211, 213, 215, 217, 219, 221,
+    223, 225, 227, 229, 231, 234, 236, 238, 240, 242, 244, 246, 248, 251, 253, 255,
+];
+
+// How each `LedArray` index is driven
+#[derive(Clone, Copy)]
+enum Output {
+    // TIM1 channel 1-4
+    Hardware(usize),
+    // Index into `SOFTWARE_PINS`
+    Software(usize),
+}
+
+// Clockwise from north, same order as `LedArray`
+const OUTPUTS: [Output; 8] = [
+    Output::Hardware(1), // N, PE9
+    Output::Software(0), // NE, PE10
+    Output::Hardware(2), // E, PE11
+    Output::Software(1), // SE, PE12
+    Output::Hardware(3), // S, PE13
+    Output::Hardware(4), // SW, PE14
+    Output::Software(2), // W, PE15
+    Output::Software(3), // NW, PE8
+];
+
+const HARDWARE_PINS: [u32; 4] = [9, 11, 13, 14];
+const SOFTWARE_PINS: [u32; 4] = [10, 12, 15, 8];
+
+// Duty cycle of the software LEDs, after gamma correction
+static SOFTWARE_DUTY: [AtomicU8; 4] = [
+    AtomicU8::new(0),
+    AtomicU8::new(0),
+    AtomicU8::new(0),
+    AtomicU8::new(0),
+];
+
+/// The eight user LEDs, with a brightness instead of on / off
+pub struct LedPwm {
+    tim1: TIM1,
+    tim16: TIM16,
+    brightness: [u8; 8],
+}
+
+impl LedPwm {
+    /// Switches the hardware LEDs to TIM1, the software ones to TIM16, and starts the PWM, all
+    /// LEDs start off
+    pub fn new(leds: LedArray, tim1: TIM1, tim16: TIM16, clocks: Clocks) -> Self {
+        interrupt::free(|_| {
+            // NOTE(unsafe) inside a critical section, and `leds` proves we own these pins
+            let (rcc, gpioe) = unsafe { (&*pac::RCC::ptr(), &*GPIOE::ptr()) };
+
+            rcc.apb2enr
+                .modify(|_, w| w.tim1en().set_bit().tim16en().set_bit());
+
+            // MODER = 0b10: alternate function, AF2: TIM1
+            for pin in HARDWARE_PINS {
+                gpioe.moder.modify(|r, w| unsafe {
+                    w.bits(r.bits() & !(0b11 << (2 * pin)) | (0b10 << (2 * pin)))
+                });
+                gpioe.afrh.modify(|r, w| unsafe {
+                    w.bits(r.bits() & !(0b1111 << (4 * (pin - 8))) | (2 << (4 * (pin - 8))))
+                });
+            }
+        });
+        drop(leds);
+
+        // TIM1 and TIM16 are on APB2
+        let timer_clock = timer_clock(clocks.pclk2().0, clocks.ppre2());
+        let divider =
+            ((timer_clock + PERIOD * PWM_FREQUENCY / 2) / (PERIOD * PWM_FREQUENCY)).max(1);
+
+        tim1.psc.write(|w| w.psc().bits(divider as u16 - 1));
+        tim1.arr.write(|w| w.arr().bits(PERIOD as u16 - 1));
+        // OCxM = 0b110: PWM mode 1 (high while CNT < CCRx), OCxPE: new duty cycles are applied
+        // at the next update
+        tim1.ccmr1_output().write(|w| unsafe { w.bits(0x6868) });
+        tim1.ccmr2_output().write(|w| unsafe { w.bits(0x6868) });
+        // CCxE: enable the four outputs
+        tim1.ccer.write(|w| unsafe { w.bits(0x1111) });
+        // MOE: TIM1 is an advanced timer, its outputs also have a main switch
+        tim1.bdtr.modify(|_, w| w.moe().set_bit());
+        tim1.egr.write(|w| w.ug().set_bit());
+        tim1.cr1.write(|w| w.arpe().set_bit().cen().set_bit());
+
+        // No prescaler, the step is at most a few thousand ticks even at 72 MHz
+        let rate = software_pwm_frequency(clocks.hclk().0);
+        let step = (timer_clock + rate / 2) / rate;
+        tim16.arr.write(|w| w.arr().bits(step as u16 - 1));
+        // The software PWM steps once per period, `update_software` enables the interrupt
+        tim16.cr1.write(|w| w.cen().set_bit());
+
+        // NOTE(unsafe) `on_update` only touches the software LEDs and TIM16's SR
+        unsafe { NVIC::unmask(Interrupt::TIM1_UP_TIM16) };
+
+        let mut pwm = LedPwm {
+            tim1,
+            tim16,
+            brightness: [0; 8],
+        };
+        pwm.set_all(0);
+        pwm
+    }
+
+    /// Sets the brightness of an LED, 0 is off and 255 fully on
+    ///
+    /// `led` is a `Direction`, or an index in `LedArray` order: clockwise starting with the north
+    /// one
+    pub fn set_brightness(&mut self, led: impl Into<usize>, brightness: u8) {
+        self.set(led.into(), brightness);
+        self.update_software();
+    }
+
+    // `set_brightness`, without `update_software`
+    fn set(&mut self, index: usize, brightness: u8) {
+        let duty = GAMMA[usize::from(brightness)];
+
+        match OUTPUTS[index] {
+            // NOTE(unsafe) any value is a valid CCR, the ones above ARR keep the output high
+            Output::Hardware(1) => self.tim1.ccr1.write(|w| unsafe { w.bits(duty.into()) }),
+            Output::Hardware(2) => self.tim1.ccr2.write(|w| unsafe { w.bits(duty.into()) }),
+            Output::Hardware(3) => self.tim1.ccr3.write(|w| unsafe { w.bits(duty.into()) }),
+            Output::Hardware(_) => self.tim1.ccr4.write(|w| unsafe { w.bits(duty.into()) }),
+            Output::Software(slot) => SOFTWARE_DUTY[slot].store(duty, Ordering::Relaxed),
+        }
+
+        self.brightness[index] = brightness;
+    }
+
+    /// The brightness last set on an LED
+    pub fn brightness(&self, led: impl Into<usize>) -> u8 {
+        self.brightness[led.into()]
+    }
+
+    pub fn set_all(&mut self, brightness: u8) {
+        for index in 0..OUTPUTS.len() {
+            self.set(index, brightness);
+        }
+        self.update_software();
+    }
+
+    /// Shows a frame of a `compass_leds` animation
+    pub fn show(&mut self, frame: &Frame) {
+        for (index, brightness) in frame.iter().enumerate() {
+            self.set(index, *brightness);
+        }
+        self.update_software();
+    }
+
+    // Every software PWM step wakes the core up, so the TIM16 interrupt is only enabled while a
+    // software LED is dimmed. Otherwise the LEDs are switched right here, if the interrupt is
+    // still pending it sets them the same way
+    fn update_software(&mut self) {
+        let dimmed = SOFTWARE_DUTY
+            .iter()
+            .any(|duty| !matches!(duty.load(Ordering::Relaxed), 0 | 255));
+
+        self.tim16.dier.write(|w| w.uie().bit(dimmed));
+        if dimmed {
+            return;
+        }
+
+        let mut bsrr = 0;
+        for (slot, pin) in SOFTWARE_PINS.iter().enumerate() {
+            bsrr |= match SOFTWARE_DUTY[slot].load(Ordering::Relaxed) {
+                // BRx: reset
+                0 => 1 << (pin + 16),
+                // BSx: set
+                _ => 1 << pin,
+            };
+        }
+
+        // NOTE(unsafe) BSRR writes are atomic and only touch the software LED pins
+        let gpioe = unsafe { &*GPIOE::ptr() };
+        gpioe.bsrr.write(|w| unsafe { w.bits(bsrr) });
+    }
+}
+
+/// Rate at which the software PWM steps with a core clock of `hclk` Hz
+///
+/// `SOFTWARE_PWM_FREQUENCY` from 32 MHz up, below that one step every `MIN_STEP_CYCLES`: 8 kHz at
+/// 8 MHz, where the dimmest levels blink at about 31 Hz and visibly flicker. Use a PLL profile
+/// for smooth dimming of the software LEDs
+pub fn software_pwm_frequency(hclk: u32) -> u32 {
+    SOFTWARE_PWM_FREQUENCY.min(hclk / MIN_STEP_CYCLES)
+}
+
+/// Must be called from the TIM1_UP_TIM16 interrupt handler
+///
+/// Steps the software LEDs: each one keeps a running sum of its duty cycle and is on for the
+/// steps where the sum wraps, so over 255 steps it is on for `duty` of them, spread as evenly as
+/// possible
+pub fn on_update() {
+    // Only ever accessed from this function, which only runs in the interrupt handler
+    static mut SUMS: [u16; 4] = [0; 4];
+
+    // NOTE(unsafe) read and clear only
+    let tim16 = unsafe { &*TIM16::ptr() };
+    if tim16.sr.read().uif().bit_is_clear() {
+        return;
+    }
+    tim16.sr.modify(|_, w| w.uif().clear_bit());
+
+    // NOTE(unsafe) see `SUMS`
+    let sums = unsafe { &mut SUMS };
+
+    let mut bsrr = 0;
+    for (slot, pin) in SOFTWARE_PINS.iter().enumerate() {
+        sums[slot] += u16::from(SOFTWARE_DUTY[slot].load(Ordering::Relaxed));
+        if sums[slot] >= PERIOD as u16 {
+            sums[slot] -= PERIOD as u16;
+            // BSx: set
+            bsrr |= 1 << pin;
+        } else {
+            // BRx: reset
+            bsrr |= 1 << (pin + 16);
+        }
+    }
+
+    // NOTE(unsafe) BSRR writes are atomic and only touch the software LED pins
+    let gpioe = unsafe { &*GPIOE::ptr() };
+    gpioe.bsrr.write(|w| unsafe { w.bits(bsrr) });
+}
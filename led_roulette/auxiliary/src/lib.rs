@@ -1,33 +1,58 @@
-
 //! Initialization code
 
 #![no_std]
 
 pub use panic_itm; // panic handler
 
-pub use cortex_m_rt::entry;
-
+pub use aux_common::delay::Delay;
+pub use clocks::ClockProfile;
+pub use compass_leds::{animation, Animation, CompassLeds, Direction, Frame, Pattern};
+pub use cortex_m_rt::{entry, exception};
+pub use led_pwm::{self as pwm, LedArray, LedPwm};
 pub use stm32f3_discovery::{leds::Leds, stm32f3xx_hal, switch_hal};
-pub use switch_hal::{ActiveHigh, OutputSwitch, Switch, ToggleableOutputSwitch};
-
-use stm32f3xx_hal::prelude::*;
 pub use stm32f3xx_hal::{
-    delay::Delay,
     gpio::{gpioe, Output, PushPull},
     hal::blocking::delay::DelayMs,
-    pac,
+    pac::{self, interrupt},
 };
+pub use switch_hal::{ActiveHigh, OutputSwitch, Switch, ToggleableOutputSwitch};
+
+pub mod time;
 
-pub type LedArray = [Switch<gpioe::PEx<Output<PushPull>>, ActiveHigh>; 8];
+use clocks::HSE_FREQUENCY;
+use stm32f3xx_hal::{
+    prelude::*,
+    rcc::{Clocks, CFGR},
+};
 
-pub fn init() -> (Delay, LedArray) {
+pub fn init(profile: ClockProfile) -> (Delay, LedArray) {
+    let (delay, leds, _, _, _) = setup(profile);
+
+    (delay, leds)
+}
+
+/// `init`, with the LEDs dimmable instead of on / off. The TIM1_UP_TIM16 interrupt has to be
+/// forwarded to `pwm::on_update`
+pub fn init_pwm(profile: ClockProfile) -> (Delay, LedPwm) {
+    let (delay, leds, clocks, tim1, tim16) = setup(profile);
+
+    (delay, LedPwm::new(leds, tim1, tim16, clocks))
+}
+
+fn setup(profile: ClockProfile) -> (Delay, LedArray, Clocks, pac::TIM1, pac::TIM16) {
     let device_periphs = pac::Peripherals::take().unwrap();
     let mut reset_and_clock_control = device_periphs.RCC.constrain();
 
     let core_periphs = cortex_m::Peripherals::take().unwrap();
     let mut flash = device_periphs.FLASH.constrain();
-    let clocks = reset_and_clock_control.cfgr.freeze(&mut flash.acr);
-    let delay = Delay::new(core_periphs.SYST, clocks);
+    let clocks = configure_clocks(reset_and_clock_control.cfgr, &profile).freeze(&mut flash.acr);
+    profile
+        .check(clocks.sysclk().0, clocks.hclk().0, clocks.pclk1().0, clocks.pclk2().0)
+        .unwrap_or_else(|mismatch| panic!("{}", mismatch));
+    // Sleeps until the SysTick exception, see `delay`
+    let delay = Delay::new(core_periphs.SYST, clocks.hclk().0);
+    // For `time::millis`
+    time::start(device_periphs.TIM2, clocks);
 
     // initialize user leds
     let mut gpioe = device_periphs.GPIOE.split(&mut reset_and_clock_control.ahb);
@@ -44,5 +69,26 @@ pub fn init() -> (Delay, LedArray) {
         &mut gpioe.otyper,
     );
 
-    (delay, leds.into_array())
-}
\ No newline at end of file
+    (
+        delay,
+        leds.into_array(),
+        clocks,
+        device_periphs.TIM1,
+        device_periphs.TIM16,
+    )
+}
+
+// Hands `profile` to the HAL, which picks the PLL multiplier, the prescalers and the flash wait
+// states
+fn configure_clocks(cfgr: CFGR, profile: &ClockProfile) -> CFGR {
+    let cfgr = if profile.uses_hse() {
+        cfgr.use_hse(HSE_FREQUENCY.Hz()).bypass_hse()
+    } else {
+        cfgr
+    };
+
+    cfgr.sysclk(profile.sysclk.Hz())
+        .hclk(profile.hclk.Hz())
+        .pclk1(profile.pclk1.Hz())
+        .pclk2(profile.pclk2.Hz())
+}
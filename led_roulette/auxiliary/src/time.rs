@@ -0,0 +1,65 @@
+//! Millisecond clock for the animations, counted by TIM2
+//!
+//! TIM2 runs freely at `TICK_FREQUENCY` and never interrupts, so unlike a tick interrupt it
+//! doesn't wake the core up from WFI. Its 32-bit counter wraps after about 5 days, `millis`
+//! extends it to 64 bits as long as it is called at least once per wrap:
+//!
+//! ``` ignore
+//! let now = aux5::time::millis();
+//! ```
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use clocks::timer_clock;
+use cortex_m::interrupt;
+use stm32f3_discovery::stm32f3xx_hal::{
+    pac::{self, TIM2},
+    rcc::Clocks,
+};
+
+/// Rate at which TIM2 counts. Low enough for the 16-bit prescaler at any timer clock, and all
+/// the `ClockProfile`s are multiples of it
+pub const TICK_FREQUENCY: u32 = 10_000;
+
+// Upper 32 bits of the extended tick count
+static HIGH: AtomicU32 = AtomicU32::new(0);
+// CNT when the extended tick count was last updated, a smaller value means it has wrapped
+static LAST: AtomicU32 = AtomicU32::new(0);
+
+/// Powers on TIM2 and starts counting, from 0
+pub(crate) fn start(tim2: TIM2, clocks: Clocks) {
+    interrupt::free(|_| {
+        // NOTE(unsafe) inside a critical section, and we only set our own bit
+        let rcc = unsafe { &*pac::RCC::ptr() };
+        rcc.apb1enr.modify(|_, w| w.tim2en().set_bit());
+    });
+
+    // TIM2 is on APB1, ARR is left at its reset value: the whole 32 bits
+    let clock = timer_clock(clocks.pclk1().0, clocks.ppre1());
+    tim2.psc
+        .write(|w| w.psc().bits((clock / TICK_FREQUENCY).max(1) as u16 - 1));
+    // UG: Load the prescaler now, not at the first wrap
+    tim2.egr.write(|w| w.ug().set_bit());
+    tim2.cr1.write(|w| w.cen().set_bit());
+}
+
+/// Milliseconds since `init`
+pub fn millis() -> u64 {
+    // NOTE(unsafe) read only, `start` has given TIM2 up
+    let tim2 = unsafe { &*TIM2::ptr() };
+
+    let ticks = interrupt::free(|_| {
+        let low = tim2.cnt.read().bits();
+        let mut high = HIGH.load(Ordering::Relaxed);
+
+        if low < LAST.load(Ordering::Relaxed) {
+            high += 1;
+            HIGH.store(high, Ordering::Relaxed);
+        }
+        LAST.store(low, Ordering::Relaxed);
+
+        u64::from(high) << 32 | u64::from(low)
+    });
+
+    ticks * 1_000 / u64::from(TICK_FREQUENCY)
+}
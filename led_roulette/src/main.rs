@@ -2,31 +2,63 @@
 #![no_main]
 #![no_std]
 
-use aux5::{entry, Delay, DelayMs, LedArray, OutputSwitch};
+use aux5::{
+    animation::Keyframes, entry, exception, interrupt, pwm, time, Animation, ClockProfile, DelayMs,
+    Pattern,
+};
+
+// How long each pattern is shown, in ms
+const PATTERN_TIME: u64 = 5_000;
+// Time between two frames, in ms
+const FRAME_TIME: u8 = 10;
+
+// The patterns are shown in turn
+fn pattern(index: usize) -> Pattern {
+    match index % 6 {
+        0 => Pattern::Keyframes(Keyframes::roulette()),
+        1 => Pattern::Chase { step: 80, tail: 3 },
+        2 => Pattern::Bounce { step: 100 },
+        3 => Pattern::Breathe { period: 2_000 },
+        4 => Pattern::Spin { step: 60 },
+        _ => Pattern::Sparkle { step: 150, density: 48 },
+    }
+}
 
 #[entry]
 fn main() -> ! {
-    let (mut delay, mut leds): (Delay, LedArray) = aux5::init();
+    // Needs the ST-LINK MCO as HSE, the PWM prescaler and the delay follow the clock
+    let (mut delay, mut leds) = aux5::init_pwm(ClockProfile::PLL_48MHZ);
 
-    // let mut half_period = 500_u16;
-    // The compiler is smart and recognized that half_period didn't change and instead, in the two
-    // places where `delay.delay_ms(half_period);` is called we see `mov.w r1, #500`. So changing
-    // half_period when debuging ((gdb) set half_period = ...)
-    // let v_half_period = Volatile::new(&mut half_period);
+    let mut index = 0;
+    let mut animation = Animation::new(pattern(index));
+    let mut next_pattern = PATTERN_TIME;
 
     loop {
-        // Turn light on for 150ms, Turn a new light on every 100ms
-        for i in 0..leds.len() {
-            // Turn on an led
-            leds[i].on().ok();
-            // Wait 100ms
-            delay.delay_ms(100u16);
-            // Turn on the next led (if we are at the last led, turn on the first one)
-            leds[if i + 1 > leds.len() - 1 { 0 } else { i + 1 } ].on().ok();
-            // Wait 50ms
-            delay.delay_ms(50u16);
-            // Turn off the first led we turned on
-            leds[i].off().ok();
+        let now = time::millis();
+
+        // Speed and direction can change at any time, the animation carries on from where it was
+        if now >= next_pattern {
+            index += 1;
+            animation.set_pattern(pattern(index));
+            animation.reverse();
+            animation.set_speed(if index % 2 == 0 { 100 } else { 150 });
+            next_pattern = now + PATTERN_TIME;
         }
+
+        leds.show(&animation.tick(now));
+
+        // The animation doesn't block, this is where the firmware would do its other work. There
+        // is nothing else to do here, so sleep until it is time for the next frame. Only SysTick
+        // wakes the core up, and the software PWM while a software LED is dimmed
+        delay.delay_ms(FRAME_TIME);
     }
 }
+
+// `delay` only needs SysTick to wake the core up from WFI, there is nothing to do here
+#[exception]
+fn SysTick() {}
+
+#[interrupt]
+fn TIM1_UP_TIM16() {
+    pwm::on_update();
+}
@@ -0,0 +1,471 @@
+//! COBS framed binary protocol
+//!
+//! Every frame is laid out as
+//!
+//! ``` text
+//! | seq (1) | payload (0..=MAX_PAYLOAD) | CRC-16 of seq + payload, little endian (2) |
+//! ```
+//!
+//! COBS encoded, so it contains no zero bytes, and terminated by a 0x00 delimiter. A receiver
+//! that gets lost (a corrupted or truncated frame) is back in sync as soon as it sees the next
+//! delimiter.
+//!
+//! The CRC is CRC-16/CCITT-FALSE (polynomial 0x1021, initial value 0xFFFF, no reflection).
+//! `SoftwareCrc` computes it anywhere, the firmware can plug in the CRC peripheral instead by
+//! implementing `Crc16`.
+
+use core::fmt;
+
+use heapless::Vec;
+
+/// Largest payload a frame can carry
+pub const MAX_PAYLOAD: usize = 64;
+/// Largest frame on the wire: COBS adds one byte per 254, plus the delimiter
+pub const MAX_ENCODED_LEN: usize = cobs_max_len(MAX_RAW_LEN) + 1;
+
+// seq + payload + CRC
+const MAX_RAW_LEN: usize = 1 + MAX_PAYLOAD + 2;
+
+/// CRC-16/CCITT-FALSE polynomial
+pub const CRC_POLY: u16 = 0x1021;
+/// CRC-16/CCITT-FALSE initial value
+pub const CRC_INIT: u16 = 0xFFFF;
+/// CRC-16/CCITT-FALSE of `b"123456789"`, the standard check value. Another `Crc16` can compare
+/// against it to make sure it computes the same CRC as `SoftwareCrc`
+pub const CRC_CHECK: u16 = 0x29B1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// The payload is larger than `MAX_PAYLOAD`, or the output buffer is too small
+    TooLong,
+    /// The frame is shorter than the sequence number plus the CRC
+    TooShort,
+    /// The frame is not valid COBS
+    Encoding,
+    /// The CRC doesn't match the contents
+    Crc,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            FrameError::TooLong => "frame too long",
+            FrameError::TooShort => "frame too short",
+            FrameError::Encoding => "invalid COBS encoding",
+            FrameError::Crc => "CRC mismatch",
+        })
+    }
+}
+
+/// Computes CRC-16/CCITT-FALSE checksums
+pub trait Crc16 {
+    fn checksum(&mut self, data: &[u8]) -> u16;
+}
+
+/// Bit by bit implementation, works everywhere
+#[derive(Clone, Copy, Default)]
+pub struct SoftwareCrc;
+
+impl Crc16 for SoftwareCrc {
+    fn checksum(&mut self, data: &[u8]) -> u16 {
+        let mut crc = CRC_INIT;
+        for &byte in data {
+            crc ^= u16::from(byte) << 8;
+            for _ in 0..8 {
+                crc = if crc & 0x8000 != 0 {
+                    (crc << 1) ^ CRC_POLY
+                } else {
+                    crc << 1
+                };
+            }
+        }
+        crc
+    }
+}
+
+/// A decoded frame
+#[derive(Debug)]
+pub struct Frame<'a> {
+    pub seq: u8,
+    /// Number of frames that went missing (judging by the sequence numbers) right before this
+    /// one
+    pub missed: u8,
+    pub payload: &'a [u8],
+}
+
+/// Turns payloads into frames, numbering them as it goes
+pub struct FrameEncoder<C> {
+    crc: C,
+    seq: u8,
+}
+
+impl<C: Crc16> FrameEncoder<C> {
+    pub fn new(crc: C) -> Self {
+        FrameEncoder { crc, seq: 0 }
+    }
+
+    /// Writes the frame for `payload`, delimiter included, into `out` and returns its length
+    pub fn encode(&mut self, payload: &[u8], out: &mut [u8]) -> Result<usize, FrameError> {
+        if payload.len() > MAX_PAYLOAD {
+            return Err(FrameError::TooLong);
+        }
+
+        let mut raw: Vec<u8, MAX_RAW_LEN> = Vec::new();
+        raw.push(self.seq).ok();
+        raw.extend_from_slice(payload).ok();
+        let crc = self.crc.checksum(&raw);
+        raw.extend_from_slice(&crc.to_le_bytes()).ok();
+
+        let n = cobs_encode(&raw, out)?;
+        *out.get_mut(n).ok_or(FrameError::TooLong)? = 0;
+
+        self.seq = self.seq.wrapping_add(1);
+        Ok(n + 1)
+    }
+}
+
+/// Reassembles frames from the bytes coming off the wire
+pub struct FrameDecoder<C> {
+    crc: C,
+    buffer: Vec<u8, MAX_ENCODED_LEN>,
+    // The current frame didn't fit in `buffer`, ignore everything up to the next delimiter
+    overflow: bool,
+    // The last call returned a frame that borrows `buffer`, clear it before going on
+    complete: bool,
+    // Sequence number the next frame should have
+    next_seq: Option<u8>,
+}
+
+impl<C: Crc16> FrameDecoder<C> {
+    pub fn new(crc: C) -> Self {
+        FrameDecoder {
+            crc,
+            buffer: Vec::new(),
+            overflow: false,
+            complete: false,
+            next_seq: None,
+        }
+    }
+
+    /// Handles a received byte, returns something once a delimiter arrives
+    ///
+    /// A frame that fails to decode is reported as an error, the decoder is then ready for the
+    /// next frame
+    pub fn feed(&mut self, byte: u8) -> Option<Result<Frame<'_>, FrameError>> {
+        if self.complete {
+            self.buffer.clear();
+            self.complete = false;
+        }
+
+        if byte != 0 {
+            if self.buffer.push(byte).is_err() {
+                self.overflow = true;
+            }
+            return None;
+        }
+
+        self.complete = true;
+        if core::mem::replace(&mut self.overflow, false) {
+            return Some(Err(FrameError::TooLong));
+        }
+        if self.buffer.is_empty() {
+            // Back to back delimiters, e.g. a sender flushing the line
+            return None;
+        }
+
+        Some(self.decode())
+    }
+
+    /// Drops the bytes of a frame that hasn't been completed yet, e.g. because the sender went
+    /// quiet in the middle of it. Otherwise they would be glued to the start of the next frame
+    pub fn discard_partial(&mut self) {
+        self.buffer.clear();
+        self.overflow = false;
+        self.complete = false;
+    }
+
+    fn decode(&mut self) -> Result<Frame<'_>, FrameError> {
+        let len = cobs_decode_in_place(&mut self.buffer)?;
+        if len < 3 {
+            return Err(FrameError::TooShort);
+        }
+
+        let (raw, crc) = self.buffer[..len].split_at(len - 2);
+        if self.crc.checksum(raw) != u16::from_le_bytes([crc[0], crc[1]]) {
+            return Err(FrameError::Crc);
+        }
+
+        let seq = raw[0];
+        let missed = self.next_seq.map_or(0, |next| seq.wrapping_sub(next));
+        self.next_seq = Some(seq.wrapping_add(1));
+
+        Ok(Frame {
+            seq,
+            missed,
+            payload: &raw[1..],
+        })
+    }
+}
+
+/// Worst case size of `len` bytes once COBS encoded, without the delimiter
+pub const fn cobs_max_len(len: usize) -> usize {
+    len + len / 254 + 1
+}
+
+/// COBS encodes `data` into `out`, returns the encoded length (no delimiter is added)
+pub fn cobs_encode(data: &[u8], out: &mut [u8]) -> Result<usize, FrameError> {
+    if out.len() < cobs_max_len(data.len()) {
+        return Err(FrameError::TooLong);
+    }
+
+    // Where the code byte of the current block goes
+    let mut code_index = 0;
+    let mut code = 1;
+    let mut n = 1;
+
+    for &byte in data {
+        if byte != 0 {
+            out[n] = byte;
+            n += 1;
+            code += 1;
+        }
+
+        if byte == 0 || code == 0xFF {
+            out[code_index] = code;
+            code_index = n;
+            n += 1;
+            code = 1;
+        }
+    }
+    out[code_index] = code;
+
+    Ok(n)
+}
+
+// Decodes a COBS block (without its delimiter) in place, returns the decoded length
+fn cobs_decode_in_place(buffer: &mut [u8]) -> Result<usize, FrameError> {
+    let mut read = 0;
+    let mut write = 0;
+
+    while read < buffer.len() {
+        let code = buffer[read] as usize;
+        if code == 0 || read + code > buffer.len() {
+            return Err(FrameError::Encoding);
+        }
+        read += 1;
+
+        for _ in 1..code {
+            buffer[write] = buffer[read];
+            read += 1;
+            write += 1;
+        }
+
+        // A block shorter than 254 bytes stands for a zero, unless it's the last one
+        if code != 0xFF && read != buffer.len() {
+            buffer[write] = 0;
+            write += 1;
+        }
+    }
+
+    Ok(write)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(data: &[u8]) {
+        let mut encoded = [0; 512];
+        let n = cobs_encode(data, &mut encoded).unwrap();
+
+        assert!(n <= cobs_max_len(data.len()));
+        assert!(!encoded[..n].contains(&0), "zero in {:?}", &encoded[..n]);
+
+        let len = cobs_decode_in_place(&mut encoded[..n]).unwrap();
+        assert_eq!(&encoded[..len], data);
+    }
+
+    #[test]
+    fn cobs_round_trip() {
+        // No zeros, so 254 and 255 bytes need a second block
+        let data: std::vec::Vec<u8> = (0..255).map(|i| i as u8 % 255 + 1).collect();
+
+        for len in [0, 1, 253, 254, 255] {
+            round_trip(&data[..len]);
+            round_trip(&[0; 255][..len]);
+        }
+        round_trip(&[0, 1, 0, 0, 2, 0]);
+    }
+
+    #[test]
+    fn cobs_errors() {
+        let mut out = [0; 4];
+        assert_eq!(
+            cobs_encode(&[1, 2, 3, 4], &mut out),
+            Err(FrameError::TooLong)
+        );
+
+        // The first block claims 5 bytes, there are only 3
+        assert_eq!(
+            cobs_decode_in_place(&mut [5, 1, 2]),
+            Err(FrameError::Encoding)
+        );
+        assert_eq!(
+            cobs_decode_in_place(&mut [2, 1, 0]),
+            Err(FrameError::Encoding)
+        );
+    }
+
+    #[test]
+    fn crc_check_value() {
+        assert_eq!(SoftwareCrc.checksum(b"123456789"), CRC_CHECK);
+        assert_eq!(CRC_CHECK, 0x29B1);
+        assert_eq!(SoftwareCrc.checksum(b""), CRC_INIT);
+    }
+
+    // A decoded frame as (seq, missed, payload)
+    type Decoded = Result<(u8, u8, std::vec::Vec<u8>), FrameError>;
+
+    // Feeds `bytes` and returns the results of every delimiter
+    fn feed_all(decoder: &mut FrameDecoder<SoftwareCrc>, bytes: &[u8]) -> std::vec::Vec<Decoded> {
+        let mut results = std::vec::Vec::new();
+        for &byte in bytes {
+            if let Some(result) = decoder.feed(byte) {
+                results.push(result.map(|frame| (frame.seq, frame.missed, frame.payload.to_vec())));
+            }
+        }
+        results
+    }
+
+    fn encode(encoder: &mut FrameEncoder<SoftwareCrc>, payload: &[u8]) -> std::vec::Vec<u8> {
+        let mut out = [0; MAX_ENCODED_LEN];
+        let n = encoder.encode(payload, &mut out).unwrap();
+        out[..n].to_vec()
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut encoder = FrameEncoder::new(SoftwareCrc);
+        let mut decoder = FrameDecoder::new(SoftwareCrc);
+
+        let mut wire = encode(&mut encoder, b"hello");
+        wire.extend(encode(&mut encoder, &[]));
+        wire.extend(encode(&mut encoder, &[0; MAX_PAYLOAD]));
+
+        assert_eq!(
+            feed_all(&mut decoder, &wire),
+            [
+                Ok((0, 0, b"hello".to_vec())),
+                Ok((1, 0, vec![])),
+                Ok((2, 0, vec![0; MAX_PAYLOAD])),
+            ]
+        );
+    }
+
+    #[test]
+    fn payload_too_long() {
+        let mut encoder = FrameEncoder::new(SoftwareCrc);
+        let mut out = [0; MAX_ENCODED_LEN];
+
+        assert_eq!(
+            encoder.encode(&[1; MAX_PAYLOAD + 1], &mut out),
+            Err(FrameError::TooLong)
+        );
+        assert_eq!(
+            encoder.encode(b"hi", &mut out[..4]),
+            Err(FrameError::TooLong)
+        );
+    }
+
+    #[test]
+    fn corrupted_frame_is_rejected() {
+        let mut encoder = FrameEncoder::new(SoftwareCrc);
+        let mut decoder = FrameDecoder::new(SoftwareCrc);
+
+        let mut wire = encode(&mut encoder, b"first");
+        // Flip a payload byte, it stays non-zero so the frame still ends at its delimiter
+        wire[3] ^= 0x01;
+        wire.extend(encode(&mut encoder, b"second"));
+
+        assert_eq!(
+            feed_all(&mut decoder, &wire),
+            [Err(FrameError::Crc), Ok((1, 0, b"second".to_vec()))]
+        );
+    }
+
+    #[test]
+    fn resync_after_garbage() {
+        let mut encoder = FrameEncoder::new(SoftwareCrc);
+        let mut decoder = FrameDecoder::new(SoftwareCrc);
+
+        // Garbage too long for the buffer, a delimiter, then a frame
+        let mut wire = vec![0x55; MAX_ENCODED_LEN + 10];
+        wire.push(0);
+        wire.extend([0, 0]);
+        wire.extend(encode(&mut encoder, b"ok"));
+
+        assert_eq!(
+            feed_all(&mut decoder, &wire),
+            [Err(FrameError::TooLong), Ok((0, 0, b"ok".to_vec()))]
+        );
+
+        // The tail of a frame whose start was lost
+        assert_eq!(
+            feed_all(&mut decoder, &[2, 1, 0]),
+            [Err(FrameError::TooShort)]
+        );
+    }
+
+    #[test]
+    fn discard_partial() {
+        let mut encoder = FrameEncoder::new(SoftwareCrc);
+        let mut decoder = FrameDecoder::new(SoftwareCrc);
+
+        let first = encode(&mut encoder, b"lost");
+        feed_all(&mut decoder, &first[..3]);
+        decoder.discard_partial();
+
+        assert_eq!(
+            feed_all(&mut decoder, &encode(&mut encoder, b"kept")),
+            [Ok((1, 0, b"kept".to_vec()))]
+        );
+    }
+
+    #[test]
+    fn sequence_gap() {
+        let mut encoder = FrameEncoder::new(SoftwareCrc);
+        let mut decoder = FrameDecoder::new(SoftwareCrc);
+
+        let mut wire = encode(&mut encoder, b"a");
+        // Two frames lost on the way
+        encode(&mut encoder, b"b");
+        encode(&mut encoder, b"c");
+        wire.extend(encode(&mut encoder, b"d"));
+        wire.extend(encode(&mut encoder, b"e"));
+
+        assert_eq!(
+            feed_all(&mut decoder, &wire),
+            [
+                Ok((0, 0, b"a".to_vec())),
+                Ok((3, 2, b"d".to_vec())),
+                Ok((4, 0, b"e".to_vec())),
+            ]
+        );
+    }
+
+    #[test]
+    fn sequence_wraps() {
+        let mut encoder = FrameEncoder::new(SoftwareCrc);
+        let mut decoder = FrameDecoder::new(SoftwareCrc);
+
+        let mut wire = std::vec::Vec::new();
+        for _ in 0..257 {
+            wire.extend(encode(&mut encoder, b"x"));
+        }
+
+        let results = feed_all(&mut decoder, &wire);
+        assert_eq!(results.len(), 257);
+        assert!(results.iter().all(|result| matches!(result, Ok((_, 0, _)))));
+        assert_eq!(results[256], Ok((0, 0, b"x".to_vec())));
+    }
+}
@@ -0,0 +1,9 @@
+//! Serial protocol shared by the firmware and the host CLI
+//!
+//! `framing` turns payloads into COBS frames and back, `message` defines what goes in the
+//! payloads.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod framing;
+pub mod message;
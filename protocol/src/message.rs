@@ -0,0 +1,241 @@
+//! Messages carried in the payload of a frame
+//!
+//! The first byte of the payload is the message tag, the rest depends on the message. Numbers
+//! are little endian.
+
+use core::{fmt, str};
+
+use crate::framing::MAX_PAYLOAD;
+
+const TAG_COMMAND: u8 = 0x01;
+const TAG_REPLY: u8 = 0x02;
+const TAG_DONE: u8 = 0x03;
+const TAG_SAMPLE: u8 = 0x04;
+
+/// Longest text a `Command` or `Reply` can carry
+pub const MAX_TEXT: usize = MAX_PAYLOAD - 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Message<'a> {
+    /// Host to board: a shell command line
+    Command(&'a str),
+    /// Board to host: part of the output of a command, long output is split over several
+    /// replies
+    Reply(&'a str),
+    /// Board to host: the command has finished, nothing else will be sent for it
+    Done,
+    /// Board to host: a sensor reading
+    Sample(Sample),
+}
+
+/// A three axis sensor reading, e.g. from the magnetometer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sample {
+    /// When the reading was taken, in microseconds since boot
+    pub timestamp_us: u32,
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+}
+
+impl Sample {
+    const LEN: usize = 4 + 3 * 2;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageError {
+    /// The payload is empty
+    Empty,
+    /// The tag doesn't match any message
+    UnknownTag(u8),
+    /// The payload is too short (or too long) for its message
+    Length,
+    /// The text of a `Command` or `Reply` is not valid UTF-8
+    Utf8,
+    /// The message doesn't fit in the output buffer
+    TooLong,
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MessageError::Empty => f.write_str("empty message"),
+            MessageError::UnknownTag(tag) => write!(f, "unknown message tag 0x{:02x}", tag),
+            MessageError::Length => f.write_str("wrong message length"),
+            MessageError::Utf8 => f.write_str("message text is not valid UTF-8"),
+            MessageError::TooLong => f.write_str("message too long"),
+        }
+    }
+}
+
+impl<'a> Message<'a> {
+    /// Parses the payload of a frame
+    pub fn parse(payload: &'a [u8]) -> Result<Self, MessageError> {
+        let (&tag, body) = payload.split_first().ok_or(MessageError::Empty)?;
+
+        match tag {
+            TAG_COMMAND => Ok(Message::Command(text(body)?)),
+            TAG_REPLY => Ok(Message::Reply(text(body)?)),
+            TAG_DONE if body.is_empty() => Ok(Message::Done),
+            TAG_SAMPLE if body.len() == Sample::LEN => {
+                let i16_at = |i: usize| i16::from_le_bytes([body[i], body[i + 1]]);
+
+                Ok(Message::Sample(Sample {
+                    timestamp_us: u32::from_le_bytes([body[0], body[1], body[2], body[3]]),
+                    x: i16_at(4),
+                    y: i16_at(6),
+                    z: i16_at(8),
+                }))
+            }
+            TAG_DONE | TAG_SAMPLE => Err(MessageError::Length),
+            _ => Err(MessageError::UnknownTag(tag)),
+        }
+    }
+
+    /// Writes the message into `out`, returns the payload length
+    pub fn write(&self, out: &mut [u8]) -> Result<usize, MessageError> {
+        let mut payload = Payload { out, len: 0 };
+
+        match *self {
+            Message::Command(text) => {
+                payload.push(&[TAG_COMMAND])?;
+                payload.push(text.as_bytes())?;
+            }
+            Message::Reply(text) => {
+                payload.push(&[TAG_REPLY])?;
+                payload.push(text.as_bytes())?;
+            }
+            Message::Done => payload.push(&[TAG_DONE])?,
+            Message::Sample(sample) => {
+                payload.push(&[TAG_SAMPLE])?;
+                payload.push(&sample.timestamp_us.to_le_bytes())?;
+                payload.push(&sample.x.to_le_bytes())?;
+                payload.push(&sample.y.to_le_bytes())?;
+                payload.push(&sample.z.to_le_bytes())?;
+            }
+        }
+
+        if payload.len > MAX_PAYLOAD {
+            return Err(MessageError::TooLong);
+        }
+        Ok(payload.len)
+    }
+}
+
+fn text(body: &[u8]) -> Result<&str, MessageError> {
+    str::from_utf8(body).map_err(|_| MessageError::Utf8)
+}
+
+// Appends to an output buffer, failing if it runs out of room
+struct Payload<'a> {
+    out: &'a mut [u8],
+    len: usize,
+}
+
+impl Payload<'_> {
+    fn push(&mut self, bytes: &[u8]) -> Result<(), MessageError> {
+        let end = self.len + bytes.len();
+        self.out
+            .get_mut(self.len..end)
+            .ok_or(MessageError::TooLong)?
+            .copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(message: Message) {
+        let mut payload = [0; MAX_PAYLOAD];
+        let len = message.write(&mut payload).unwrap();
+
+        assert_eq!(Message::parse(&payload[..len]), Ok(message));
+    }
+
+    #[test]
+    fn every_message() {
+        round_trip(Message::Command("reverse héllo"));
+        round_trip(Message::Command(""));
+        round_trip(Message::Reply("olléh"));
+        round_trip(Message::Reply(&"x".repeat(MAX_TEXT)));
+        round_trip(Message::Done);
+        round_trip(Message::Sample(Sample {
+            timestamp_us: u32::MAX,
+            x: i16::MIN,
+            y: -1,
+            z: i16::MAX,
+        }));
+    }
+
+    #[test]
+    fn layout() {
+        let mut payload = [0; MAX_PAYLOAD];
+        let sample = Message::Sample(Sample {
+            timestamp_us: 0x0403_0201,
+            x: 0x0605,
+            y: -2,
+            z: 0,
+        });
+
+        let len = sample.write(&mut payload).unwrap();
+        assert_eq!(
+            &payload[..len],
+            [TAG_SAMPLE, 1, 2, 3, 4, 5, 6, 0xFE, 0xFF, 0, 0]
+        );
+
+        let len = Message::Command("hi").write(&mut payload).unwrap();
+        assert_eq!(&payload[..len], [TAG_COMMAND, b'h', b'i']);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Message::parse(&[]), Err(MessageError::Empty));
+        assert_eq!(Message::parse(&[0x7F]), Err(MessageError::UnknownTag(0x7F)));
+        assert_eq!(Message::parse(&[0]), Err(MessageError::UnknownTag(0)));
+        assert_eq!(Message::parse(&[TAG_DONE, 0]), Err(MessageError::Length));
+        assert_eq!(Message::parse(&[TAG_SAMPLE]), Err(MessageError::Length));
+        assert_eq!(
+            Message::parse(&[TAG_SAMPLE, 1, 2, 3, 4, 5, 6, 7, 8, 9]),
+            Err(MessageError::Length)
+        );
+        assert_eq!(
+            Message::parse(&[TAG_SAMPLE, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]),
+            Err(MessageError::Length)
+        );
+        assert_eq!(
+            Message::parse(&[TAG_REPLY, 0xC3, 0x28]),
+            Err(MessageError::Utf8)
+        );
+    }
+
+    #[test]
+    fn write_errors() {
+        let mut payload = [0; 2 * MAX_PAYLOAD];
+
+        // Fits in the buffer, but not in a frame
+        let text = "x".repeat(MAX_TEXT + 1);
+        assert_eq!(
+            Message::Command(&text).write(&mut payload),
+            Err(MessageError::TooLong)
+        );
+
+        assert_eq!(
+            Message::Reply("hello").write(&mut payload[..5]),
+            Err(MessageError::TooLong)
+        );
+        assert_eq!(
+            Message::Sample(Sample {
+                timestamp_us: 0,
+                x: 0,
+                y: 0,
+                z: 0,
+            })
+            .write(&mut payload[..Sample::LEN]),
+            Err(MessageError::TooLong)
+        );
+        assert_eq!(Message::Done.write(&mut []), Err(MessageError::TooLong));
+    }
+}
@@ -6,6 +6,7 @@
 use panic_itm as _; // panic handler
 
 pub use cortex_m::{asm::bkpt, iprint, iprintln, peripheral::ITM};
+pub use compass_leds::Direction;
 pub use cortex_m_rt::entry;
 
 pub use stm32f3::stm32f303::{self, gpioc::RegisterBlock};
@@ -4,7 +4,7 @@
 use core::ptr;
 
 #[allow(unused_imports)]
-use aux7::{entry, iprint, iprintln, ITM};
+use aux7::{entry, iprint, iprintln, Direction, ITM};
 
 /// Print the current contents of ODR
 fn iprint_odr(itm: &mut ITM) {
@@ -152,6 +152,12 @@ fn main() -> ! {
         gpioe.bsrr.write(|w| w.bs11().set_bit());
         gpioe.bsrr.write(|w| w.br9().set_bit());
         gpioe.bsrr.write(|w| w.br11().set_bit());
+
+        // Or in compass terms, `Direction` knows which pin each LED is on
+        gpioe.bsrr.write(|w| w.bits(1 << Direction::N.pin()));
+        gpioe.bsrr.write(|w| w.bits(1 << Direction::E.pin()));
+        gpioe.bsrr.write(|w| w.bits(1 << (Direction::N.pin() + 16)));
+        gpioe.bsrr.write(|w| w.bits(1 << (Direction::E.pin() + 16)));
     }
 
     loop {}
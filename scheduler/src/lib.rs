@@ -0,0 +1,334 @@
+//! Software timers and a cooperative task scheduler
+//!
+//! Instead of one blocking `loop` with `delay_ms` calls, the application is split into tasks that
+//! each do a bit of work and return:
+//!
+//! ``` ignore
+//! static TASKS: &[Task<App>] = &[
+//!     Task { name: "serial", priority: 2, schedule: Schedule::Always, run: poll_serial },
+//!     Task { name: "blink", priority: 1, schedule: Schedule::Every(100), run: blink },
+//! ];
+//!
+//! let mut scheduler: Scheduler<App, 4> = Scheduler::new();
+//! for task in TASKS {
+//!     scheduler.spawn(*task, now()).ok();
+//! }
+//!
+//! loop {
+//!     scheduler.poll(now(), &mut app);
+//! }
+//! ```
+//!
+//! `C` is whatever the tasks need access to. Times are milliseconds, e.g. from
+//! `MonoTimer::now().as_millis()`. Nothing is preempted: a task that takes long delays all the
+//! others.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod timer;
+
+pub use timer::{Mode, Timer};
+
+use heapless::Vec;
+
+/// Signature of a task, `C` is the context shared by all tasks
+pub type TaskFn<C> = fn(&mut C);
+
+/// When a task runs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    /// Every time the scheduler is polled, for tasks that poll something themselves
+    Always,
+    /// Every that many milliseconds
+    Every(u64),
+    /// Once, that many milliseconds after it was spawned (or restarted)
+    After(u64),
+}
+
+pub struct Task<C> {
+    /// Only used to tell tasks apart when debugging
+    pub name: &'static str,
+    /// Among the tasks that are due, higher priorities run first
+    pub priority: u8,
+    pub schedule: Schedule,
+    pub run: TaskFn<C>,
+}
+
+// `derive` would require `C: Clone`
+impl<C> Clone for Task<C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C> Copy for Task<C> {}
+
+/// Identifies a spawned task
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TaskId(u32);
+
+struct Entry<C> {
+    id: TaskId,
+    task: Task<C>,
+    // `None` for `Schedule::Always`
+    timer: Option<Timer>,
+}
+
+impl<C> Entry<C> {
+    fn is_due(&mut self, now: u64) -> bool {
+        match &mut self.timer {
+            Some(timer) => timer.poll(now),
+            None => true,
+        }
+    }
+}
+
+/// Runs up to `N` tasks
+pub struct Scheduler<C, const N: usize> {
+    // Sorted by priority, highest first, tasks with the same priority in the order they were
+    // spawned
+    entries: Vec<Entry<C>, N>,
+    next_id: u32,
+}
+
+impl<C, const N: usize> Scheduler<C, N> {
+    pub const fn new() -> Self {
+        Scheduler {
+            entries: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Adds a task, its timer (if any) starts at `now`
+    ///
+    /// Gives the task back if there are already `N` tasks
+    pub fn spawn(&mut self, task: Task<C>, now: u64) -> Result<TaskId, Task<C>> {
+        if self.entries.is_full() {
+            return Err(task);
+        }
+
+        let id = TaskId(self.next_id);
+        self.next_id += 1;
+
+        let mut timer = match task.schedule {
+            Schedule::Always => None,
+            Schedule::Every(interval) => Some(Timer::periodic(interval)),
+            Schedule::After(delay) => Some(Timer::one_shot(delay)),
+        };
+        if let Some(timer) = &mut timer {
+            timer.start(now);
+        }
+
+        let index = self
+            .entries
+            .iter()
+            .position(|entry| entry.task.priority < task.priority)
+            .unwrap_or(self.entries.len());
+        // Can't fail, there is room
+        self.entries.insert(index, Entry { id, task, timer }).ok();
+
+        Ok(id)
+    }
+
+    /// Removes a task, returns it if it existed
+    pub fn remove(&mut self, id: TaskId) -> Option<Task<C>> {
+        let index = self.entries.iter().position(|entry| entry.id == id)?;
+        Some(self.entries.remove(index).task)
+    }
+
+    /// Starts the timer of a task over from `now`, this is how a finished `Schedule::After` task
+    /// is run again
+    pub fn restart(&mut self, id: TaskId, now: u64) {
+        if let Some(timer) = self.timer(id) {
+            timer.start(now);
+        }
+    }
+
+    /// Stops the timer of a task, it doesn't run again until it is restarted. `Schedule::Always`
+    /// tasks can't be stopped, remove them instead
+    pub fn stop(&mut self, id: TaskId) {
+        if let Some(timer) = self.timer(id) {
+            timer.stop();
+        }
+    }
+
+    fn timer(&mut self, id: TaskId) -> Option<&mut Timer> {
+        self.entries
+            .iter_mut()
+            .find(|entry| entry.id == id)?
+            .timer
+            .as_mut()
+    }
+
+    /// Runs every task that is due, highest priority first, each at most once. Returns the number
+    /// of tasks that ran
+    pub fn poll(&mut self, now: u64, ctx: &mut C) -> usize {
+        let mut ran = 0;
+        for entry in self.entries.iter_mut() {
+            if entry.is_due(now) {
+                (entry.task.run)(ctx);
+                ran += 1;
+            }
+        }
+        ran
+    }
+
+    /// The earliest deadline of all running timers, `None` if there is none
+    ///
+    /// `Schedule::Always` tasks are always due, this doesn't account for them
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.timer.as_ref()?.deadline())
+            .min()
+    }
+}
+
+impl<C, const N: usize> Default for Scheduler<C, N> {
+    fn default() -> Self {
+        Scheduler::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The tasks log their name, so the tests can check what ran in which order
+    type Log = std::vec::Vec<&'static str>;
+
+    fn task(name: &'static str, priority: u8, schedule: Schedule) -> Task<Log> {
+        // `run` can't capture `name`, so there is one function per name
+        let run: TaskFn<Log> = match name {
+            "a" => |log| log.push("a"),
+            "b" => |log| log.push("b"),
+            "c" => |log| log.push("c"),
+            _ => |log| log.push("d"),
+        };
+
+        Task {
+            name,
+            priority,
+            schedule,
+            run,
+        }
+    }
+
+    fn spawn<const N: usize>(
+        scheduler: &mut Scheduler<Log, N>,
+        task: Task<Log>,
+        now: u64,
+    ) -> TaskId {
+        match scheduler.spawn(task, now) {
+            Ok(id) => id,
+            Err(task) => panic!("no room for {}", task.name),
+        }
+    }
+
+    fn poll<const N: usize>(scheduler: &mut Scheduler<Log, N>, now: u64) -> Log {
+        let mut log = Log::new();
+        let ran = scheduler.poll(now, &mut log);
+        assert_eq!(ran, log.len());
+        log
+    }
+
+    #[test]
+    fn priority_order() {
+        let mut scheduler: Scheduler<Log, 4> = Scheduler::new();
+        spawn(&mut scheduler, task("a", 1, Schedule::Always), 0);
+        spawn(&mut scheduler, task("b", 3, Schedule::Always), 0);
+        spawn(&mut scheduler, task("c", 1, Schedule::Always), 0);
+        spawn(&mut scheduler, task("d", 2, Schedule::Always), 0);
+
+        // Same priority: in the order they were spawned
+        assert_eq!(poll(&mut scheduler, 0), ["b", "d", "a", "c"]);
+    }
+
+    #[test]
+    fn schedules() {
+        let mut scheduler: Scheduler<Log, 4> = Scheduler::new();
+        spawn(&mut scheduler, task("a", 1, Schedule::Every(10)), 0);
+        spawn(&mut scheduler, task("b", 2, Schedule::After(15)), 0);
+        spawn(&mut scheduler, task("c", 0, Schedule::Always), 0);
+
+        assert_eq!(poll(&mut scheduler, 5), ["c"]);
+        assert_eq!(poll(&mut scheduler, 10), ["a", "c"]);
+        assert_eq!(poll(&mut scheduler, 15), ["b", "c"]);
+        // Late: `a` runs once for the deadlines at 20 and 30
+        assert_eq!(poll(&mut scheduler, 35), ["a", "c"]);
+        assert_eq!(poll(&mut scheduler, 40), ["a", "c"]);
+    }
+
+    #[test]
+    fn full() {
+        let mut scheduler: Scheduler<Log, 2> = Scheduler::new();
+
+        assert!(scheduler.spawn(task("a", 1, Schedule::Always), 0).is_ok());
+        assert!(scheduler.spawn(task("b", 1, Schedule::Always), 0).is_ok());
+        let task = scheduler
+            .spawn(task("c", 1, Schedule::Always), 0)
+            .unwrap_err();
+        assert_eq!(task.name, "c");
+
+        assert_eq!(poll(&mut scheduler, 0), ["a", "b"]);
+    }
+
+    #[test]
+    fn remove() {
+        let mut scheduler: Scheduler<Log, 2> = Scheduler::new();
+        let a = spawn(&mut scheduler, task("a", 1, Schedule::Always), 0);
+        spawn(&mut scheduler, task("b", 1, Schedule::Always), 0);
+
+        assert_eq!(scheduler.remove(a).map(|task| task.name), Some("a"));
+        assert!(scheduler.remove(a).is_none());
+        assert_eq!(poll(&mut scheduler, 0), ["b"]);
+
+        // There is room again, and the new task doesn't get the old id
+        let c = spawn(&mut scheduler, task("c", 1, Schedule::Always), 0);
+        assert_ne!(c, a);
+    }
+
+    #[test]
+    fn restart_and_stop() {
+        let mut scheduler: Scheduler<Log, 2> = Scheduler::new();
+        let a = spawn(&mut scheduler, task("a", 1, Schedule::After(10)), 0);
+        let b = spawn(&mut scheduler, task("b", 1, Schedule::Every(10)), 0);
+
+        assert_eq!(poll(&mut scheduler, 10), ["a", "b"]);
+        assert_eq!(poll(&mut scheduler, 20), ["b"]);
+
+        scheduler.restart(a, 20);
+        scheduler.stop(b);
+        assert_eq!(poll(&mut scheduler, 29), Log::new());
+        assert_eq!(poll(&mut scheduler, 30), ["a"]);
+        assert_eq!(poll(&mut scheduler, 100), Log::new());
+
+        // Restarting moves the deadline, it doesn't add a second one
+        scheduler.restart(b, 100);
+        scheduler.restart(b, 105);
+        assert_eq!(poll(&mut scheduler, 110), Log::new());
+        assert_eq!(poll(&mut scheduler, 115), ["b"]);
+    }
+
+    #[test]
+    fn next_deadline() {
+        let mut scheduler: Scheduler<Log, 4> = Scheduler::new();
+        assert_eq!(scheduler.next_deadline(), None);
+
+        spawn(&mut scheduler, task("a", 1, Schedule::Always), 0);
+        assert_eq!(scheduler.next_deadline(), None);
+
+        let b = spawn(&mut scheduler, task("b", 1, Schedule::Every(30)), 0);
+        let c = spawn(&mut scheduler, task("c", 1, Schedule::After(20)), 5);
+        assert_eq!(scheduler.next_deadline(), Some(25));
+
+        poll(&mut scheduler, 25);
+        assert_eq!(scheduler.next_deadline(), Some(30));
+
+        scheduler.stop(b);
+        assert_eq!(scheduler.next_deadline(), None);
+
+        scheduler.restart(c, 40);
+        assert_eq!(scheduler.next_deadline(), Some(60));
+    }
+}
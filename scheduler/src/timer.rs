@@ -0,0 +1,163 @@
+//! Software timers
+//!
+//! A `Timer` doesn't do anything by itself, it has to be polled with the current time. Times are
+//! in milliseconds, measured from whatever point the caller likes (e.g. boot).
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Expires once, `interval` after it was started
+    OneShot,
+    /// Expires every `interval`
+    Periodic,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Timer {
+    mode: Mode,
+    interval: u64,
+    // When the timer expires next, `None` while it is stopped
+    deadline: Option<u64>,
+}
+
+impl Timer {
+    /// Creates a stopped timer
+    pub const fn new(mode: Mode, interval: u64) -> Self {
+        Timer {
+            mode,
+            interval,
+            deadline: None,
+        }
+    }
+
+    pub const fn one_shot(interval: u64) -> Self {
+        Timer::new(Mode::OneShot, interval)
+    }
+
+    pub const fn periodic(interval: u64) -> Self {
+        Timer::new(Mode::Periodic, interval)
+    }
+
+    /// (Re)starts the timer, it expires `interval` after `now`
+    pub fn start(&mut self, now: u64) {
+        self.deadline = Some(now + self.interval);
+    }
+
+    pub fn stop(&mut self) {
+        self.deadline = None;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.deadline.is_some()
+    }
+
+    /// When the timer expires next, `None` if it is stopped
+    pub fn deadline(&self) -> Option<u64> {
+        self.deadline
+    }
+
+    /// Returns `true` if the timer has expired, once per expiry
+    ///
+    /// A periodic timer is rearmed relative to its previous deadline, so it doesn't drift when it
+    /// is polled late. If it is polled so late that whole periods were missed, those are skipped
+    /// instead of being reported in a burst.
+    pub fn poll(&mut self, now: u64) -> bool {
+        let deadline = match self.deadline {
+            Some(deadline) if deadline <= now => deadline,
+            _ => return false,
+        };
+
+        self.deadline = match self.mode {
+            Mode::OneShot => None,
+            // A zero interval would never catch up with `now`
+            Mode::Periodic if self.interval == 0 => Some(now),
+            Mode::Periodic => {
+                let missed = (now - deadline) / self.interval;
+                Some(deadline + (missed + 1) * self.interval)
+            }
+        };
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The times in `0..until` at which `timer` expires, polled every ms
+    fn expiries(timer: &mut Timer, until: u64) -> std::vec::Vec<u64> {
+        (0..until).filter(|&now| timer.poll(now)).collect()
+    }
+
+    #[test]
+    fn stopped() {
+        let mut timer = Timer::periodic(10);
+
+        assert!(!timer.is_running());
+        assert_eq!(timer.deadline(), None);
+        assert!(!timer.poll(100));
+    }
+
+    #[test]
+    fn periodic() {
+        let mut timer = Timer::periodic(10);
+        timer.start(5);
+
+        assert_eq!(timer.deadline(), Some(15));
+        assert_eq!(expiries(&mut timer, 50), [15, 25, 35, 45]);
+    }
+
+    #[test]
+    fn periodic_polled_late() {
+        let mut timer = Timer::periodic(10);
+        timer.start(0);
+
+        // Late by less than a period: the next deadline doesn't drift
+        assert!(timer.poll(13));
+        assert_eq!(timer.deadline(), Some(20));
+        // Periods at 20, 30 and 40 missed, they are reported once
+        assert!(timer.poll(47));
+        assert!(!timer.poll(49));
+        assert_eq!(timer.deadline(), Some(50));
+        // Exactly on a deadline
+        assert!(timer.poll(70));
+        assert_eq!(timer.deadline(), Some(80));
+    }
+
+    #[test]
+    fn zero_interval() {
+        let mut timer = Timer::periodic(0);
+        timer.start(3);
+
+        assert!(timer.poll(3));
+        assert!(timer.poll(3));
+        assert!(timer.poll(1_000));
+        assert_eq!(timer.deadline(), Some(1_000));
+    }
+
+    #[test]
+    fn one_shot() {
+        let mut timer = Timer::one_shot(10);
+        timer.start(0);
+
+        assert_eq!(expiries(&mut timer, 100), [10]);
+        assert!(!timer.is_running());
+
+        timer.start(100);
+        assert!(!timer.poll(109));
+        assert!(timer.poll(200));
+        assert!(!timer.poll(300));
+    }
+
+    #[test]
+    fn stop_and_restart() {
+        let mut timer = Timer::periodic(10);
+        timer.start(0);
+        timer.stop();
+
+        assert!(!timer.poll(10));
+
+        timer.start(15);
+        assert_eq!(expiries(&mut timer, 40), [25, 35]);
+    }
+}
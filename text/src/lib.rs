@@ -0,0 +1,7 @@
+//! Text handling shared by the firmware, kept apart so it can be tested on the host
+//!
+//! `reverse` reverses UTF-8 text without breaking up what reads as a single character.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod reverse;
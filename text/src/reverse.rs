@@ -0,0 +1,175 @@
+//! UTF-8 aware text reversal
+//!
+//! Reversing the bytes of a UTF-8 string scrambles every multi-byte character, and even
+//! reversing `char`s detaches accents and emoji modifiers from the character they belong to.
+//! This reverses clusters instead: a base character together with the combining marks, variation
+//! selectors and skin tone modifiers that follow it, and characters glued together with a zero
+//! width joiner (e.g. family emoji) stay together, and so do the two regional indicator letters of
+//! a flag. It is not full Unicode grapheme segmentation but covers what people actually type.
+//!
+//! The input is a `&str`, so it is valid UTF-8: the echo_server shell rejects invalid lines
+//! before they get here.
+
+const ZWJ: char = '\u{200D}';
+
+/// Iterator over the clusters of a string, last one first
+pub struct Reversed<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Reversed<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let mut chars = self.rest.char_indices().rev().peekable();
+        let mut start = chars.peek()?.0;
+
+        while let Some((i, c)) = chars.next() {
+            start = i;
+
+            // These belong to the character before them
+            if c == ZWJ || is_extend(c) {
+                continue;
+            }
+
+            // A base character, unless a ZWJ glues it to the one before
+            if let Some(&(_, ZWJ)) = chars.peek() {
+                continue;
+            }
+
+            // Regional indicators pair up from the start of a run of them, so this one is the
+            // second letter of a flag if an odd number of them comes before it
+            if is_regional_indicator(c) {
+                let before = self.rest[..i]
+                    .chars()
+                    .rev()
+                    .take_while(|&c| is_regional_indicator(c))
+                    .count();
+
+                if before % 2 == 1 {
+                    if let Some((i, _)) = chars.next() {
+                        start = i;
+                    }
+                }
+            }
+
+            break;
+        }
+
+        let cluster = &self.rest[start..];
+        self.rest = &self.rest[..start];
+        Some(cluster)
+    }
+}
+
+/// Returns the clusters of `s` in reverse order, concatenating them gives the reversed text
+pub fn reverse(s: &str) -> Reversed<'_> {
+    Reversed { rest: s }
+}
+
+// Characters that extend the cluster of the preceding character
+fn is_extend(c: char) -> bool {
+    matches!(c,
+        // Combining diacritical marks (and their extensions and supplement)
+        '\u{0300}'..='\u{036F}'
+        | '\u{1AB0}'..='\u{1AFF}'
+        | '\u{1DC0}'..='\u{1DFF}'
+        // Combining marks for symbols
+        | '\u{20D0}'..='\u{20FF}'
+        // Variation selectors (e.g. text vs emoji presentation)
+        | '\u{FE00}'..='\u{FE0F}'
+        // Combining half marks
+        | '\u{FE20}'..='\u{FE2F}'
+        // Emoji skin tone modifiers
+        | '\u{1F3FB}'..='\u{1F3FF}'
+        // Emoji tag sequences (subdivision flags)
+        | '\u{E0020}'..='\u{E007F}'
+    )
+}
+
+// The letters that flags are made of, e.g. U+1F1EB U+1F1F7 (F R) is the French flag
+fn is_regional_indicator(c: char) -> bool {
+    matches!(c, '\u{1F1E6}'..='\u{1F1FF}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::reverse;
+
+    fn reversed(s: &str) -> String {
+        reverse(s).collect()
+    }
+
+    #[test]
+    fn ascii() {
+        assert_eq!(reversed("hello, world"), "dlrow ,olleh");
+        assert_eq!(reversed("a"), "a");
+        assert_eq!(reversed(""), "");
+    }
+
+    #[test]
+    fn multi_byte_characters() {
+        assert_eq!(reversed("añb€c"), "c€bña");
+        assert_eq!(reversed("日本語"), "語本日");
+    }
+
+    #[test]
+    fn combining_marks_stay_on_their_character() {
+        // "e" + COMBINING ACUTE ACCENT, "a" + COMBINING DIAERESIS + COMBINING MACRON
+        assert_eq!(reversed("xe\u{301}y"), "ye\u{301}x");
+        assert_eq!(reversed("a\u{308}\u{304}b"), "ba\u{308}\u{304}");
+        // A mark with nothing before it is a cluster of its own
+        assert_eq!(reversed("\u{301}ab"), "ba\u{301}");
+    }
+
+    #[test]
+    fn variation_selectors() {
+        // HEAVY BLACK HEART + VARIATION SELECTOR-16
+        assert_eq!(reversed("a\u{2764}\u{FE0F}b"), "b\u{2764}\u{FE0F}a");
+    }
+
+    #[test]
+    fn zwj_sequences() {
+        // Family: man, woman, girl
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let text = format!("a{}b", family);
+
+        assert_eq!(reversed(&text), format!("b{}a", family));
+        assert_eq!(reverse(&text).nth(1), Some(family));
+    }
+
+    #[test]
+    fn skin_tones() {
+        // Waving hand + medium skin tone, thumbs up + dark skin tone
+        let wave = "\u{1F44B}\u{1F3FD}";
+        let thumbs = "\u{1F44D}\u{1F3FF}";
+
+        assert_eq!(
+            reversed(&format!("{}{}", wave, thumbs)),
+            format!("{}{}", thumbs, wave)
+        );
+    }
+
+    #[test]
+    fn flags() {
+        let fr = "\u{1F1EB}\u{1F1F7}";
+        let de = "\u{1F1E9}\u{1F1EA}";
+        let jp = "\u{1F1EF}\u{1F1F5}";
+
+        assert_eq!(reversed(fr), fr);
+        assert_eq!(reversed(&format!("{}{}", fr, de)), format!("{}{}", de, fr));
+        assert_eq!(
+            reversed(&format!("x{}{}{}y", fr, de, jp)),
+            format!("y{}{}{}x", jp, de, fr)
+        );
+    }
+
+    #[test]
+    fn unpaired_regional_indicator() {
+        // The lone letter is the last one, the first two make a flag
+        let fr = "\u{1F1EB}\u{1F1F7}";
+        let a = "\u{1F1E6}";
+
+        assert_eq!(reversed(&format!("{}{}", fr, a)), format!("{}{}", a, fr));
+    }
+}
@@ -0,0 +1,57 @@
+//! COBS framed binary protocol, see `protocol::framing`
+//!
+//! This adds `HardwareCrc`, which computes the frame CRC with the STM32F3 CRC peripheral.
+
+pub use protocol::framing::*;
+
+use stm32f3_discovery::stm32f3xx_hal::pac::{self, CRC};
+
+/// Uses the STM32F3 CRC peripheral, set up for a 16 bit polynomial
+pub struct HardwareCrc {
+    crc: &'static pac::crc::RegisterBlock,
+}
+
+impl HardwareCrc {
+    /// Powers on and configures the CRC peripheral
+    ///
+    /// Several `HardwareCrc`s can share the peripheral as long as none of them is used from an
+    /// interrupt handler, every `checksum` starts from scratch
+    ///
+    /// Panics if the peripheral doesn't compute the same CRC as `SoftwareCrc`, every frame
+    /// exchanged with the host would fail otherwise
+    pub fn new() -> Self {
+        // NOTE(unsafe) we only set a bit that nothing else in aux11 uses
+        unsafe { (*pac::RCC::ptr()).ahbenr.modify(|_, w| w.crcen().set_bit()) };
+        // NOTE(unsafe) the CRC peripheral is not used anywhere else
+        let crc = unsafe { &*CRC::ptr() };
+
+        crc.pol.write(|w| unsafe { w.bits(u32::from(CRC_POLY)) });
+        crc.init.write(|w| unsafe { w.bits(u32::from(CRC_INIT)) });
+        // POLYSIZE = 0b01: 16 bit polynomial, REV_IN / REV_OUT are left cleared
+        crc.cr.write(|w| unsafe { w.polysize().bits(0b01) });
+
+        let mut hardware = HardwareCrc { crc };
+        assert_eq!(
+            hardware.checksum(b"123456789"),
+            CRC_CHECK,
+            "the CRC peripheral doesn't compute CRC-16/CCITT-FALSE"
+        );
+        hardware
+    }
+}
+
+impl Crc16 for HardwareCrc {
+    fn checksum(&mut self, data: &[u8]) -> u16 {
+        // RESET: Load INIT into DR
+        self.crc.cr.modify(|_, w| w.reset().set_bit());
+
+        // A byte wide write to DR only feeds that one byte into the calculation
+        let dr = &self.crc.dr as *const _ as *mut u8;
+        for &byte in data {
+            // NOTE(unsafe) DR is byte addressable
+            unsafe { core::ptr::write_volatile(dr, byte) };
+        }
+
+        self.crc.dr.read().bits() as u16
+    }
+}
@@ -0,0 +1,113 @@
+//! Millisecond time base for `async` code, driven by TIM6
+//!
+//! After `start` TIM6 interrupts every millisecond, the application has to forward that
+//! interrupt to `on_interrupt`:
+//!
+//! ``` ignore
+//! #[interrupt]
+//! fn TIM6_DACUNDER() {
+//!     aux11::time::on_interrupt();
+//! }
+//! ```
+
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU32, Ordering},
+    task::{Context, Poll},
+};
+
+use cortex_m::peripheral::NVIC;
+use executor::Signal;
+use stm32f3_discovery::stm32f3xx_hal::pac::{self, Interrupt, TIM6};
+
+// Frequency of the clock that drives TIM6, set by `init`
+static TIMER_CLOCK: AtomicU32 = AtomicU32::new(0);
+// Milliseconds since `start`
+static MILLIS: AtomicU32 = AtomicU32::new(0);
+// Woken every millisecond
+static TICK: Signal = Signal::new();
+
+pub(crate) fn set_clock(clock: u32) {
+    TIMER_CLOCK.store(clock, Ordering::Relaxed);
+}
+
+/// Powers on TIM6 and starts counting milliseconds
+pub fn start() {
+    // NOTE(unsafe) we only set a bit that nothing else in aux11 uses
+    unsafe { (*pac::RCC::ptr()).apb1enr.modify(|_, w| w.tim6en().set_bit()) };
+    // NOTE(unsafe) TIM6 is not used anywhere else
+    let tim6 = unsafe { &*TIM6::ptr() };
+
+    // Overflow every millisecond: as little prescaling as fits the 16 bit counter, so that this
+    // also works with a timer clock below 1 MHz (e.g. PCLK1 = HSI / 16)
+    let (psc, arr) = prescale(TIMER_CLOCK.load(Ordering::Relaxed) / 1_000);
+    tim6.psc.write(|w| w.psc().bits(psc));
+    tim6.arr.write(|w| w.arr().bits(arr));
+    // UG: Load the prescaler now, not at the first overflow. That sets UIF, clear it again
+    tim6.egr.write(|w| w.ug().set_bit());
+    tim6.sr.write(|w| w.uif().clear_bit());
+
+    // UIE: Interrupt on every overflow
+    tim6.dier.write(|w| w.uie().set_bit());
+    tim6.cr1.write(|w| w.cen().set_bit());
+
+    unsafe { NVIC::unmask(Interrupt::TIM6_DACUNDER) };
+}
+
+// PSC and ARR that make TIM6 overflow every `ticks` ticks of its clock (rounded down to a
+// multiple of the prescaler). With ARR = 0 the counter would never overflow, hence at least 2
+fn prescale(ticks: u32) -> (u16, u16) {
+    let ticks = ticks.max(2);
+    let prescaler = (ticks + 0xFFFF) / 0x1_0000;
+    let reload = ticks / prescaler;
+
+    ((prescaler - 1) as u16, (reload - 1) as u16)
+}
+
+/// Milliseconds since `start`, wraps after about 49 days
+pub fn now() -> u32 {
+    MILLIS.load(Ordering::Relaxed)
+}
+
+/// Must be called from the TIM6_DACUNDER interrupt handler
+pub fn on_interrupt() {
+    // NOTE(unsafe) only the update flag is touched
+    let tim6 = unsafe { &*TIM6::ptr() };
+    tim6.sr.write(|w| w.uif().clear_bit());
+
+    MILLIS.fetch_add(1, Ordering::Relaxed);
+    TICK.wake();
+}
+
+/// A future that completes at a given time
+pub struct Timer {
+    deadline: u32,
+}
+
+impl Timer {
+    /// Completes `ms` milliseconds from now, e.g. `Timer::after(100).await`
+    pub fn after(ms: u32) -> Self {
+        Timer::at(now().wrapping_add(ms))
+    }
+
+    /// Completes once `now()` reaches `deadline`
+    pub fn at(deadline: u32) -> Self {
+        Timer { deadline }
+    }
+}
+
+impl Future for Timer {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        TICK.register(cx.waker());
+
+        // Compare as signed so that this still works when `now` wraps
+        if now().wrapping_sub(self.deadline) as i32 >= 0 {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
@@ -1,8 +1,8 @@
 #![no_main]
 #![no_std]
 
-use core::fmt::{self, Write};
-use aux11::{entry, usart1};
+use core::fmt::Write;
+use aux11::{entry, exception, interrupt, serial::{SerialConfig, SerialPort}, ClockProfile};
 
 macro_rules! uprint {
     ($serial:expr, $($arg:tt)*) => {
@@ -19,43 +19,36 @@ macro_rules! uprintln {
     };
 }
 
-struct SerialPort {
-    usart1: &'static mut usart1::RegisterBlock,
-}
-
-impl fmt::Write for SerialPort {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        for byte in s.bytes() {
-            // Wait until its safe to write to TDR
-            while self.usart1.isr.read().txe().bit_is_clear() {}
-
-            // Write a byte 
-            self
-                .usart1
-                .tdr
-                .write(|w| w.tdr().bits(byte as u16));
-        }
-
-        Ok(())
-    }
-}
-
 #[entry]
 fn main() -> ! {
-    let (usart1, _mono_timer, _itm) = aux11::init();
+    let (usart1, _mono_timer, _itm, _leds, _magnetometer) =
+        aux11::init(ClockProfile::HSI_8MHZ, SerialConfig::default());
+
+    let mut serial = SerialPort::new(usart1);
 
     // Wait until there's data available
-    while usart1.isr.read().rxne().bit_is_clear() {}
-    
-    // Retrieve the data
-    let _byte = usart1.rdr.read().rdr().bits() as u8;
+    let _byte = serial.read_byte();
 
     aux11::bkpt();
 
-    let mut serial = SerialPort { usart1 };
-
     uprintln!(serial, "The answer is {}", 40 + 2);
+    serial.flush();
 
 
     loop {}
-}
\ No newline at end of file
+}
+
+#[exception]
+fn SysTick() {
+    aux11::monotimer::on_systick();
+}
+
+#[interrupt]
+fn DMA1_CH4() {
+    aux11::dma::on_transfer_complete();
+}
+
+#[interrupt]
+fn USART1_EXTI25() {
+    aux11::serial::on_interrupt();
+}